@@ -1,23 +1,24 @@
 use std::env::args;
 
-use async_uring::{Result, net::tcp::TcpStream, rt::UringRuntime, tokio::TokioAsyncFd};
-use tokio::{
-	io::{AsyncReadExt, AsyncWriteExt},
-	net::TcpListener,
-	task::coop::unconstrained,
+use async_uring::{
+	Result,
+	net::tcp::{TcpListener, TcpStream},
+	rt::UringRuntime,
+	tokio::TokioAsyncFd,
 };
+use tokio::task::coop::unconstrained;
 
 async_uring_bench::tokio_main! {
 	let (rt, fut) = UringRuntime::builder::<TokioAsyncFd>().build()?;
 
 	tokio::spawn(unconstrained(fut));
 
-	let listener = TcpListener::bind(args().nth(2).unwrap()).await?;
+	let addr = args().nth(2).unwrap().parse()?;
+	let mut listener = TcpListener::bind(&rt, addr).await?;
 
 	println!("listening");
 
 	while let Ok((stream, addr)) = listener.accept().await {
-		let stream = rt.register_tcp(stream.into_std()?).await?;
 		println!("accepted {addr:?}");
 		tokio::spawn(handle(stream));
 	}
@@ -26,15 +27,19 @@ async_uring_bench::tokio_main! {
 }
 
 async fn handle(mut stream: TcpStream) -> Result<()> {
-	let mut buf = vec![0u8; 16 * 1024];
+	let mut buf = Vec::with_capacity(16 * 1024);
 
 	loop {
-		let cnt = stream.read(&mut buf).await?;
+		buf.clear();
+		let (ret, read) = stream.read(buf).await;
+		let cnt = ret?;
 
 		if cnt == 0 {
 			break Ok(());
 		}
 
-		stream.write_all(&buf[0..cnt]).await?;
+		let (ret, written) = stream.write_all(read).await;
+		ret?;
+		buf = written;
 	}
 }
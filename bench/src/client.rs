@@ -7,10 +7,7 @@ use std::{
 };
 
 use async_uring::{Result, net::tcp::TcpStream, rt::UringRuntime, tokio::TokioAsyncFd};
-use tokio::{
-	io::{AsyncReadExt, AsyncWriteExt},
-	task::{JoinSet, coop::unconstrained},
-};
+use tokio::task::{JoinSet, coop::unconstrained};
 
 static COUNT: AtomicUsize = AtomicUsize::new(0);
 
@@ -30,10 +27,7 @@ async_uring_bench::tokio_main! {
 
 	let mut sockets = Vec::with_capacity(socket_cnt);
 	for _ in 0..socket_cnt {
-		sockets.push(
-			rt.register_tcp(tokio::net::TcpStream::connect(addr).await?.into_std()?)
-				.await?,
-		);
+		sockets.push(TcpStream::connect(&rt, addr).await?);
 	}
 
 	let mut set = JoinSet::new();
@@ -85,9 +79,12 @@ async fn handle(mut stream: TcpStream, size: usize) -> Result<()> {
 	let mut received = 0;
 
 	loop {
-		stream.write_all(&buf).await?;
+		let (ret, written) = stream.write_all(buf).await;
+		ret?;
 
-		let cnt = stream.read(&mut buf).await?;
+		let (ret, read) = stream.read(written).await;
+		let cnt = ret?;
+		buf = read;
 
 		received += cnt;
 
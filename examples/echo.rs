@@ -1,11 +1,13 @@
 use std::env::args;
 
-use async_uring::{Result, net::tcp::TcpStream, rt::UringRuntime, tokio::TokioAsyncFd};
-use tokio::{
-	io::{AsyncReadExt, AsyncWriteExt},
-	net::TcpListener,
-	task::coop::unconstrained,
+use async_uring::{
+	Result,
+	net::tcp::{TcpListener, TcpStream},
+	rt::UringRuntime,
+	tokio::TokioAsyncFd,
 };
+use futures::StreamExt;
+use tokio::task::coop::unconstrained;
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<()> {
@@ -13,12 +15,13 @@ async fn main() -> Result<()> {
 
 	tokio::spawn(unconstrained(fut));
 
-	let listener = TcpListener::bind(args().nth(1).unwrap()).await?;
+	let addr = args().nth(1).unwrap().parse().expect("invalid address");
+	let mut listener = TcpListener::bind(&rt, addr).await?;
 
 	println!("listening");
 
-	while let Ok((stream, addr)) = listener.accept().await {
-		let stream = rt.register_tcp(stream.into_std()?).await?;
+	let mut incoming = listener.incoming();
+	while let Some(Ok((stream, addr))) = incoming.next().await {
 		println!("accepted {addr:?}");
 		tokio::spawn(handle(stream));
 	}
@@ -27,15 +30,19 @@ async fn main() -> Result<()> {
 }
 
 async fn handle(mut stream: TcpStream) -> Result<()> {
-	let mut buf = vec![0u8; 16 * 1024];
+	let mut buf = Vec::with_capacity(16 * 1024);
 
 	loop {
-		let cnt = stream.read(&mut buf).await?;
+		buf.clear();
+		let (ret, read) = stream.read(buf).await;
+		let cnt = ret?;
 
 		if cnt == 0 {
 			break Ok(());
 		}
 
-		stream.write_all(&buf[0..cnt]).await?;
+		let (ret, written) = stream.write_all(read).await;
+		ret?;
+		buf = written;
 	}
 }
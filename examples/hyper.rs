@@ -1,11 +1,11 @@
 use std::{convert::Infallible, env::args};
 
-use async_uring::{Result, rt::UringRuntime, tokio::TokioAsyncFd};
+use async_uring::{Result, net::TcpListener, rt::UringRuntime, tokio::TokioAsyncFd};
 use bytes::Bytes;
 use http_body_util::Full;
 use hyper::{Request, Response, server::conn::http1, service::service_fn};
 use hyper_util::rt::{TokioIo, TokioTimer};
-use tokio::{net::TcpListener, task::coop::unconstrained};
+use tokio::task::coop::unconstrained;
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<()> {
@@ -13,12 +13,12 @@ async fn main() -> Result<()> {
 
 	tokio::spawn(unconstrained(fut));
 
-	let listener = TcpListener::bind(args().nth(1).unwrap()).await?;
+	let addr = args().nth(1).unwrap().parse().expect("invalid address");
+	let mut listener = TcpListener::bind(&rt, addr).await?;
 
 	println!("listening");
 
 	while let Ok((stream, addr)) = listener.accept().await {
-		let stream = rt.register_tcp(stream.into_std()?).await?;
 		//println!("accepted {addr:?}");
 		let stream = TokioIo::new(stream);
 		tokio::spawn(async move {
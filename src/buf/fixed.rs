@@ -0,0 +1,180 @@
+use std::{
+	alloc::{Layout, alloc_zeroed, dealloc},
+	fmt,
+	ops::{Deref, DerefMut},
+	ptr::NonNull,
+	sync::{Arc, Mutex},
+};
+
+use super::{IoBuf, IoBufMut};
+
+/// Memory registered with the ring through `register_buffers`, split into equally sized buffers.
+pub(crate) struct FixedBufPool {
+	ptr: NonNull<u8>,
+	layout: Layout,
+	size: usize,
+	free: Mutex<Vec<u16>>,
+}
+
+// SAFETY: the memory is only accessed through `FixedBuf`s, which are handed out at most once
+unsafe impl Send for FixedBufPool {}
+// SAFETY: see above
+unsafe impl Sync for FixedBufPool {}
+
+impl FixedBufPool {
+	pub fn new(count: u16, size: usize) -> std::io::Result<Self> {
+		let layout = usize::from(count)
+			.checked_mul(size)
+			.and_then(|total| Layout::from_size_align(total, 4096).ok())
+			.filter(|layout| layout.size() > 0)
+			.ok_or_else(|| std::io::Error::from(std::io::ErrorKind::InvalidInput))?;
+
+		// SAFETY: layout has a non-zero size
+		let ptr = NonNull::new(unsafe { alloc_zeroed(layout) })
+			.ok_or_else(|| std::io::Error::from(std::io::ErrorKind::OutOfMemory))?;
+
+		Ok(Self {
+			ptr,
+			layout,
+			size,
+			free: Mutex::new((0..count).rev().collect()),
+		})
+	}
+
+	pub fn iovecs(&self) -> Vec<libc::iovec> {
+		(0..self.layout.size() / self.size)
+			.map(|i| libc::iovec {
+				// SAFETY: every buffer is inside the allocation
+				iov_base: unsafe { self.ptr.as_ptr().add(i * self.size) }.cast(),
+				iov_len: self.size,
+			})
+			.collect()
+	}
+
+	pub fn checkout(self: &Arc<Self>) -> Option<FixedBuf> {
+		let index = self.free.lock().unwrap().pop()?;
+		Some(FixedBuf {
+			pool: self.clone(),
+			index,
+			len: 0,
+		})
+	}
+}
+
+impl Drop for FixedBufPool {
+	fn drop(&mut self) {
+		// SAFETY: allocated in `new` with the same layout
+		unsafe { dealloc(self.ptr.as_ptr(), self.layout) };
+	}
+}
+
+/// A buffer checked out from the runtime's registered buffer pool.
+///
+/// Reads and writes with this buffer use `ReadFixed`/`WriteFixed`, so the kernel doesn't have to
+/// pin its pages for every op. The buffer goes back to the pool when dropped.
+pub struct FixedBuf {
+	pool: Arc<FixedBufPool>,
+	index: u16,
+	len: usize,
+}
+
+impl FixedBuf {
+	/// Total size of the buffer.
+	pub fn capacity(&self) -> usize {
+		self.pool.size
+	}
+
+	pub fn clear(&mut self) {
+		self.len = 0;
+	}
+
+	/// Append `data` to the initialized part of the buffer.
+	///
+	/// # Panics
+	/// Panics if `data` doesn't fit in the remaining capacity.
+	pub fn extend_from_slice(&mut self, data: &[u8]) {
+		assert!(
+			data.len() <= self.capacity() - self.len,
+			"data doesn't fit in fixed buffer"
+		);
+		// SAFETY: checked that the data fits in the buffer
+		unsafe {
+			self.ptr()
+				.add(self.len)
+				.copy_from_nonoverlapping(data.as_ptr(), data.len());
+		}
+		self.len += data.len();
+	}
+
+	fn ptr(&self) -> *mut u8 {
+		// SAFETY: index is a valid buffer in the pool
+		unsafe {
+			self.pool
+				.ptr
+				.as_ptr()
+				.add(usize::from(self.index) * self.pool.size)
+		}
+	}
+}
+
+impl Deref for FixedBuf {
+	type Target = [u8];
+
+	fn deref(&self) -> &[u8] {
+		// SAFETY: the pool memory is zeroed on allocation and this buffer is only handed out once
+		unsafe { std::slice::from_raw_parts(self.ptr(), self.len) }
+	}
+}
+
+impl DerefMut for FixedBuf {
+	fn deref_mut(&mut self) -> &mut [u8] {
+		// SAFETY: see `deref`
+		unsafe { std::slice::from_raw_parts_mut(self.ptr(), self.len) }
+	}
+}
+
+impl fmt::Debug for FixedBuf {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("FixedBuf")
+			.field("index", &self.index)
+			.field("len", &self.len)
+			.field("capacity", &self.capacity())
+			.finish()
+	}
+}
+
+impl Drop for FixedBuf {
+	fn drop(&mut self) {
+		self.pool.free.lock().unwrap().push(self.index);
+	}
+}
+
+// SAFETY: the pool memory doesn't move and is kept alive by the `Arc`
+unsafe impl IoBuf for FixedBuf {
+	fn stable_ptr(&self) -> *const u8 {
+		self.ptr()
+	}
+
+	fn bytes_init(&self) -> usize {
+		self.len
+	}
+
+	fn bytes_total(&self) -> usize {
+		self.capacity()
+	}
+
+	fn buf_index(&self) -> Option<u16> {
+		Some(self.index)
+	}
+}
+
+// SAFETY: same pointer as `stable_ptr`
+unsafe impl IoBufMut for FixedBuf {
+	fn stable_mut_ptr(&mut self) -> *mut u8 {
+		self.ptr()
+	}
+
+	unsafe fn set_init(&mut self, pos: usize) {
+		self.len = self.len.max(pos);
+	}
+}
@@ -0,0 +1,159 @@
+//! Buffers that can be owned by the runtime for the duration of an operation.
+//!
+//! Owned-buffer operations move the buffer into the operation and hand it back once the kernel is
+//! done with it, also if the operation is dropped before it completes, see [`IoBuf`].
+
+mod fixed;
+mod ring;
+
+pub use fixed::FixedBuf;
+pub(crate) use fixed::FixedBufPool;
+pub use ring::ProvidedBuf;
+pub(crate) use ring::{BUF_RING_GROUP, BufRing};
+
+/// A buffer that the kernel can read from.
+///
+/// Methods that take an owned buffer hand it back with their result. If their future is dropped
+/// before the op completes, the runtime keeps the buffer alive until the kernel is done with it,
+/// along with the addresses, control messages and duplicated fds sent with it. Zero-copy sends
+/// keep it until the kernel's notification that it no longer references the buffer.
+///
+/// # Safety
+/// The pointer returned by `stable_ptr` must stay valid for `bytes_total` bytes for as long as
+/// the buffer is alive, even if the buffer itself is moved. The first `bytes_init` bytes must be
+/// initialized.
+pub unsafe trait IoBuf: Unpin + Send + 'static {
+	/// Pointer to the start of the buffer.
+	fn stable_ptr(&self) -> *const u8;
+
+	/// Number of initialized bytes, which is what gets written.
+	fn bytes_init(&self) -> usize;
+
+	/// Total size of the buffer, including uninitialized memory.
+	fn bytes_total(&self) -> usize;
+
+	/// Index of the registered buffer this buffer lives in, if any.
+	///
+	/// Reads and writes use `ReadFixed`/`WriteFixed` for registered buffers.
+	fn buf_index(&self) -> Option<u16> {
+		None
+	}
+}
+
+/// A buffer that the kernel can write into.
+///
+/// # Safety
+/// The pointer returned by `stable_mut_ptr` must be the same as the one returned by
+/// `stable_ptr`.
+pub unsafe trait IoBufMut: IoBuf {
+	/// Mutable pointer to the start of the buffer.
+	fn stable_mut_ptr(&mut self) -> *mut u8;
+
+	/// Mark the first `pos` bytes of the buffer as initialized.
+	///
+	/// # Safety
+	/// The first `pos` bytes must actually be initialized.
+	unsafe fn set_init(&mut self, pos: usize);
+}
+
+// SAFETY: the heap allocation doesn't move when the vec is moved
+unsafe impl IoBuf for Vec<u8> {
+	fn stable_ptr(&self) -> *const u8 {
+		self.as_ptr()
+	}
+
+	fn bytes_init(&self) -> usize {
+		self.len()
+	}
+
+	fn bytes_total(&self) -> usize {
+		self.capacity()
+	}
+}
+
+// SAFETY: same pointer as `stable_ptr`
+unsafe impl IoBufMut for Vec<u8> {
+	fn stable_mut_ptr(&mut self) -> *mut u8 {
+		self.as_mut_ptr()
+	}
+
+	unsafe fn set_init(&mut self, pos: usize) {
+		if self.len() < pos {
+			// SAFETY: enforced by the caller
+			unsafe { self.set_len(pos) };
+		}
+	}
+}
+
+// SAFETY: the heap allocation doesn't move when the box is moved
+unsafe impl IoBuf for Box<[u8]> {
+	fn stable_ptr(&self) -> *const u8 {
+		self.as_ptr()
+	}
+
+	fn bytes_init(&self) -> usize {
+		self.len()
+	}
+
+	fn bytes_total(&self) -> usize {
+		self.len()
+	}
+}
+
+// SAFETY: same pointer as `stable_ptr`
+unsafe impl IoBufMut for Box<[u8]> {
+	fn stable_mut_ptr(&mut self) -> *mut u8 {
+		self.as_mut_ptr()
+	}
+
+	unsafe fn set_init(&mut self, _: usize) {
+		// boxed slices are always fully initialized
+	}
+}
+
+#[cfg(feature = "bytes")]
+// SAFETY: `Bytes` is immutable and its data doesn't move when it is moved
+unsafe impl IoBuf for bytes::Bytes {
+	fn stable_ptr(&self) -> *const u8 {
+		self.as_ptr()
+	}
+
+	fn bytes_init(&self) -> usize {
+		self.len()
+	}
+
+	fn bytes_total(&self) -> usize {
+		self.len()
+	}
+}
+
+#[cfg(feature = "bytes")]
+// SAFETY: the heap allocation doesn't move when the `BytesMut` is moved
+unsafe impl IoBuf for bytes::BytesMut {
+	fn stable_ptr(&self) -> *const u8 {
+		self.as_ptr()
+	}
+
+	fn bytes_init(&self) -> usize {
+		self.len()
+	}
+
+	fn bytes_total(&self) -> usize {
+		self.capacity()
+	}
+}
+
+#[cfg(feature = "bytes")]
+// SAFETY: same pointer as `stable_ptr`
+unsafe impl IoBufMut for bytes::BytesMut {
+	fn stable_mut_ptr(&mut self) -> *mut u8 {
+		self.as_mut_ptr()
+	}
+
+	unsafe fn set_init(&mut self, pos: usize) {
+		if self.len() < pos {
+			// SAFETY: enforced by the caller
+			unsafe { self.set_len(pos) };
+		}
+	}
+}
@@ -0,0 +1,188 @@
+use std::{
+	alloc::{Layout, alloc_zeroed, dealloc},
+	fmt,
+	ops::Deref,
+	ptr::NonNull,
+	sync::{
+		Arc, Mutex,
+		atomic::{AtomicU16, Ordering},
+	},
+};
+
+use io_uring::types::BufRingEntry;
+
+use super::IoBuf;
+
+/// Buffer group id of the runtime's provided buffer ring.
+pub(crate) const BUF_RING_GROUP: u16 = 0;
+
+/// Buffers the kernel picks from for ops submitted with `BUFFER_SELECT`.
+pub(crate) struct BufRing {
+	ring: NonNull<BufRingEntry>,
+	ring_layout: Layout,
+	bufs: NonNull<u8>,
+	bufs_layout: Layout,
+	size: u32,
+	// the kernel only reads the tail, we are the only ones writing it
+	tail: Mutex<u16>,
+}
+
+// SAFETY: the ring is only written to with the tail locked, buffers are only accessed through
+// `ProvidedBuf`s, which the kernel hands out at most once
+unsafe impl Send for BufRing {}
+// SAFETY: see above
+unsafe impl Sync for BufRing {}
+
+impl BufRing {
+	pub fn new(count: u16, size: usize) -> std::io::Result<Self> {
+		let invalid = || std::io::Error::from(std::io::ErrorKind::InvalidInput);
+
+		if !count.is_power_of_two() || count > 1 << 15 {
+			return Err(invalid());
+		}
+		let size = u32::try_from(size).map_err(|_| invalid())?;
+
+		let ring_layout = Layout::array::<BufRingEntry>(count.into())
+			.and_then(|x| x.align_to(4096))
+			.map_err(|_| invalid())?;
+		let bufs_layout = (size as usize)
+			.checked_mul(count.into())
+			.and_then(|total| Layout::from_size_align(total, 4096).ok())
+			.filter(|layout| layout.size() > 0)
+			.ok_or_else(invalid)?;
+
+		// SAFETY: both layouts have a non-zero size
+		let (ring, bufs) = unsafe { (alloc_zeroed(ring_layout), alloc_zeroed(bufs_layout)) };
+		let (Some(ring), Some(bufs)) = (NonNull::new(ring.cast()), NonNull::new(bufs)) else {
+			// SAFETY: only the non-null allocations are freed, with the layout they were made with
+			unsafe {
+				if !ring.is_null() {
+					dealloc(ring, ring_layout);
+				}
+				if !bufs.is_null() {
+					dealloc(bufs, bufs_layout);
+				}
+			}
+			return Err(std::io::ErrorKind::OutOfMemory.into());
+		};
+
+		let this = Self {
+			ring,
+			ring_layout,
+			bufs,
+			bufs_layout,
+			size,
+			tail: Mutex::new(0),
+		};
+		for bid in 0..count {
+			this.recycle(bid);
+		}
+
+		Ok(this)
+	}
+
+	pub fn addr(&self) -> u64 {
+		self.ring.as_ptr().addr() as u64
+	}
+
+	pub fn entries(&self) -> u16 {
+		// checked to fit in `new`
+		#[expect(clippy::cast_possible_truncation)]
+		let entries = (self.ring_layout.size() / size_of::<BufRingEntry>()) as u16;
+		entries
+	}
+
+	fn buf_ptr(&self, bid: u16) -> *mut u8 {
+		// SAFETY: bid is always below the buffer count
+		unsafe {
+			self.bufs
+				.as_ptr()
+				.add(usize::from(bid) * self.size as usize)
+		}
+	}
+
+	/// Hand a buffer back to the kernel.
+	pub fn recycle(&self, bid: u16) {
+		let mut tail = self.tail.lock().unwrap();
+		let mask = self.entries() - 1;
+
+		// SAFETY: the index is masked to the ring size, and the kernel doesn't read entries past
+		// the tail
+		unsafe {
+			let entry = self.ring.as_ptr().add(usize::from(*tail & mask));
+			(*entry).set_addr(self.buf_ptr(bid).addr() as u64);
+			(*entry).set_len(self.size);
+			(*entry).set_bid(bid);
+		}
+		*tail = tail.wrapping_add(1);
+
+		// SAFETY: the tail is a u16 inside the first entry, which lives as long as the ring
+		let shared =
+			unsafe { AtomicU16::from_ptr(BufRingEntry::tail(self.ring.as_ptr()).cast_mut()) };
+		shared.store(*tail, Ordering::Release);
+	}
+}
+
+impl Drop for BufRing {
+	fn drop(&mut self) {
+		// SAFETY: allocated in `new` with the same layouts
+		unsafe {
+			dealloc(self.ring.as_ptr().cast(), self.ring_layout);
+			dealloc(self.bufs.as_ptr(), self.bufs_layout);
+		}
+	}
+}
+
+/// A lease on a buffer the kernel picked from the runtime's provided buffer ring.
+///
+/// The buffer goes back to the ring when dropped.
+pub struct ProvidedBuf {
+	ring: Arc<BufRing>,
+	bid: u16,
+	len: usize,
+}
+
+impl ProvidedBuf {
+	pub(crate) fn new(ring: Arc<BufRing>, bid: u16, len: usize) -> Self {
+		Self { ring, bid, len }
+	}
+}
+
+impl Deref for ProvidedBuf {
+	type Target = [u8];
+
+	fn deref(&self) -> &[u8] {
+		// SAFETY: the kernel filled `len` bytes, and won't touch the buffer until it's recycled
+		unsafe { std::slice::from_raw_parts(self.ring.buf_ptr(self.bid), self.len) }
+	}
+}
+
+impl fmt::Debug for ProvidedBuf {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("ProvidedBuf")
+			.field("bid", &self.bid)
+			.field("len", &self.len)
+			.finish()
+	}
+}
+
+impl Drop for ProvidedBuf {
+	fn drop(&mut self) {
+		self.ring.recycle(self.bid);
+	}
+}
+
+// SAFETY: the ring memory doesn't move and is kept alive by the `Arc`
+unsafe impl IoBuf for ProvidedBuf {
+	fn stable_ptr(&self) -> *const u8 {
+		self.ring.buf_ptr(self.bid)
+	}
+
+	fn bytes_init(&self) -> usize {
+		self.len
+	}
+
+	fn bytes_total(&self) -> usize {
+		self.len
+	}
+}
@@ -1,4 +1,6 @@
 use std::{
+	future::poll_fn,
+	io::{self, IoSlice},
 	mem::MaybeUninit,
 	pin::Pin,
 	task::{Context, Poll, ready},
@@ -6,128 +8,413 @@ use std::{
 
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 
-use crate::rt::operation::{OperationCancelData, ProtectedOps};
+use crate::rt::operation::{OperationCancelData, ProtectedOps, RawRead, RawWrite};
+
+/// Largest amount of data buffered by a single read or write.
+const MAX_BUF: usize = 64 * 1024;
+
 // from feature `maybe_uninit_write_slice`
 fn write_copy_of_slice<'a, T>(this: &'a mut [MaybeUninit<T>], src: &[T]) -> &'a mut [T]
 where
 	T: Copy,
 {
 	// SAFETY: &[T] and &[MaybeUninit<T>] have the same layout
-	let uninit_src: &[MaybeUninit<T>] = unsafe { std::mem::transmute(src) };
+	let uninit_src: &[MaybeUninit<T>] =
+		unsafe { &*(std::ptr::from_ref::<[T]>(src) as *const [MaybeUninit<T>]) };
 
 	this.copy_from_slice(uninit_src);
 
 	// SAFETY: Valid elements have just been copied into `self` so it is initialized
-	unsafe { &mut *(this as *mut [MaybeUninit<T>] as *mut [T]) }
+	unsafe { &mut *(std::ptr::from_mut::<[MaybeUninit<T>]>(this) as *mut [T]) }
 }
 
-#[expect(private_bounds)]
-pub struct Copying<T: Unpin + ProtectedOps> {
-	// this is only an option to allow into_inner.
-	// the only time this option will be None is when into_inner explicitly sets it
-	inner: Option<T>,
+/// Internal buffer that reads go through, so the kernel never writes into a caller's buffer.
+#[derive(Default)]
+pub(crate) struct ReadBuffer {
+	buf: Vec<u8>,
+	pos: usize,
+	reading: bool,
+}
 
-	read_buf: Vec<u8>,
-	write_buf: Vec<u8>,
+pub(crate) trait BufferedRead: RawRead {
+	fn read_buffer(&mut self) -> &mut ReadBuffer;
 }
 
-#[expect(private_bounds)]
-impl<T: Unpin + ProtectedOps> Copying<T> {
-	pub fn new(val: T) -> Self {
-		Self {
-			inner: Some(val),
-			read_buf: Vec::new(),
-			write_buf: Vec::new(),
+impl ReadBuffer {
+	pub fn poll_read<T: BufferedRead>(
+		io: &mut T,
+		cx: &mut Context<'_>,
+		buf: &mut ReadBuf<'_>,
+	) -> Poll<io::Result<()>> {
+		let this = io.read_buffer();
+		if this.pos < this.buf.len() {
+			this.copy_to(buf);
+			return Poll::Ready(Ok(()));
+		}
+		if buf.remaining() == 0 {
+			return Poll::Ready(Ok(()));
 		}
+
+		ready!(Self::poll_fill(io, cx, buf.remaining()))?;
+		io.read_buffer().copy_to(buf);
+
+		Poll::Ready(Ok(()))
 	}
 
-	/// Disable copying data into buffers.
-	///
-	/// # Safety
-	/// You must keep any buffers you provide alive for the entire duration of any operations.
-	#[allow(clippy::missing_panics_doc)]
-	pub unsafe fn into_inner(mut self) -> T {
-		self.inner.take().unwrap()
+	/// Read up to `len` bytes into the empty buffer, or finish the read already in flight.
+	fn poll_fill<T: BufferedRead>(
+		io: &mut T,
+		cx: &mut Context<'_>,
+		len: usize,
+	) -> Poll<io::Result<()>> {
+		// the buffer can't be touched while the kernel is reading into it
+		if !io.read_buffer().reading {
+			io.reclaim_op(T::READ_OP_ID);
+
+			let this = io.read_buffer();
+			this.buf.clear();
+			this.pos = 0;
+			this.buf.reserve(len.min(MAX_BUF));
+			this.reading = true;
+		}
+		let spare = io.read_buffer().buf.spare_capacity_mut();
+		let (ptr, len) = (spare.as_mut_ptr().cast::<u8>(), spare.len());
+
+		// SAFETY: the buffer is handed to the runtime if we are dropped before the op completes
+		let ret = ready!(unsafe { io.poll_read_raw(cx, ptr, len, None) });
+
+		let this = io.read_buffer();
+		this.reading = false;
+		let cnt = ret?;
+		// SAFETY: kernel just initialized these bytes in the read op
+		unsafe { this.buf.set_len(cnt) };
+
+		Poll::Ready(Ok(()))
 	}
-}
 
-impl<T: Unpin + AsyncRead + ProtectedOps> AsyncRead for Copying<T> {
-	fn poll_read(
-		mut self: Pin<&mut Self>,
+	/// Get the read op ready for a read that bypasses the buffer: finish a read that's still in
+	/// flight, so its data stays buffered instead of going to the wrong buffer.
+	pub fn poll_prepare<T: BufferedRead>(io: &mut T, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		if io.read_buffer().reading {
+			ready!(Self::poll_fill(io, cx, 0))?;
+		}
+		io.reclaim_op(T::READ_OP_ID);
+
+		Poll::Ready(Ok(()))
+	}
+
+	/// Like `poll_prepare`, but cancel a read that's still in flight instead of waiting for data,
+	/// keeping whatever it read before the cancel took effect. `interrupted` tracks the cancel
+	/// across polls, and starts out false.
+	pub fn poll_interrupt<T: BufferedRead>(
+		io: &mut T,
 		cx: &mut Context<'_>,
-		buf: &mut ReadBuf<'_>,
-	) -> Poll<std::io::Result<()>> {
-		let this = &mut *self;
+		interrupted: &mut bool,
+	) -> Poll<io::Result<()>> {
+		if io.read_buffer().reading && !*interrupted {
+			io.interrupt_op(T::READ_OP_ID);
+			*interrupted = true;
+		}
+
+		match ready!(Self::poll_prepare(io, cx)) {
+			// a read that failed on its own before our cancel keeps its error
+			Err(err) if *interrupted && err.raw_os_error() == Some(libc::ECANCELED) => {
+				Poll::Ready(Ok(()))
+			}
+			ret => Poll::Ready(ret),
+		}
+	}
+
+	/// Like `poll_interrupt`, then take everything that's buffered.
+	pub fn poll_unbuffer<T: BufferedRead>(
+		io: &mut T,
+		cx: &mut Context<'_>,
+		interrupted: &mut bool,
+	) -> Poll<io::Result<Vec<u8>>> {
+		ready!(Self::poll_interrupt(io, cx, interrupted))?;
+
+		Poll::Ready(Ok(io.read_buffer().take_remaining()))
+	}
+
+	/// Whether no data is buffered and no read is in flight.
+	pub fn is_empty(&self) -> bool {
+		!self.reading && self.pos >= self.buf.len()
+	}
+
+	/// Take the buffered bytes that weren't consumed yet, once no read is in flight.
+	pub fn take_remaining(&mut self) -> Vec<u8> {
+		debug_assert!(!self.reading);
+		let mut buf = std::mem::take(&mut self.buf);
+		buf.drain(..self.pos);
+		self.pos = 0;
+		buf
+	}
+
+	/// Copy up to `len` buffered bytes to `ptr`, consuming them unless `peek` is set.
+	///
+	/// SAFETY: `ptr` must be valid for writing `len` bytes
+	pub unsafe fn copy_out(&mut self, ptr: *mut u8, len: usize, peek: bool) -> usize {
+		let cnt = len.min(self.buf.len() - self.pos);
+		// SAFETY: enforced by the caller, and our side holds at least `cnt` bytes
+		unsafe { std::ptr::copy_nonoverlapping(self.buf.as_ptr().add(self.pos), ptr, cnt) };
+		if !peek {
+			self.pos += cnt;
+		}
+		cnt
+	}
+
+	fn copy_to(&mut self, buf: &mut ReadBuf<'_>) {
+		let cnt = buf.remaining().min(self.buf.len() - self.pos);
 
 		// SAFETY: we only copy things in
 		let unfilled = unsafe { buf.unfilled_mut() };
-		this.read_buf.reserve(unfilled.len());
+		write_copy_of_slice(&mut unfilled[..cnt], &self.buf[self.pos..self.pos + cnt]);
+		// SAFETY: we just copied these bytes in
+		unsafe { buf.assume_init(cnt) };
+		buf.advance(cnt);
+
+		self.pos += cnt;
+	}
+
+	/// Move the buffer into the runtime if a read is still in flight.
+	pub fn cancel<T: RawRead>(&mut self, io: &mut T) {
+		if self.reading {
+			self.reading = false;
+			io.cancel_op(
+				T::READ_OP_ID,
+				OperationCancelData {
+					wake: true,
+					buf: Box::new(std::mem::take(&mut self.buf)),
+					release: None,
+				},
+			);
+		}
+	}
+}
 
-		let mut inner_buf = ReadBuf::uninit(&mut this.read_buf.spare_capacity_mut()[0..unfilled.len()]);
-		ready!(Pin::new(this.inner.as_mut().unwrap()).poll_read(cx, &mut inner_buf))?;
+/// Internal buffer that writes are copied into, so the kernel never reads from a caller's buffer.
+///
+/// Writes are submitted as soon as they are buffered and their result is picked up by the next
+/// write or flush.
+#[derive(Default)]
+pub(crate) struct WriteBuffer {
+	buf: Vec<u8>,
+	pos: usize,
+	sending: bool,
+}
+
+pub(crate) trait BufferedWrite: RawWrite {
+	fn write_buffer(&mut self) -> &mut WriteBuffer;
+}
 
-		write_copy_of_slice(
-			&mut unfilled[0..inner_buf.filled().len()],
-			inner_buf.filled(),
-		);
-		unsafe {
-			buf.assume_init(inner_buf.filled().len());
+impl WriteBuffer {
+	pub fn poll_write<T: BufferedWrite>(
+		io: &mut T,
+		cx: &mut Context<'_>,
+		buf: &[u8],
+	) -> Poll<io::Result<usize>> {
+		ready!(Self::poll_flush(io, cx))?;
+		if buf.is_empty() {
+			return Poll::Ready(Ok(0));
 		}
-		buf.advance(inner_buf.filled().len());
+
+		let this = io.write_buffer();
+		let cnt = buf.len().min(MAX_BUF);
+		this.buf.extend_from_slice(&buf[..cnt]);
+
+		// start sending now, the result is picked up by the next write or flush
+		if let Poll::Ready(Err(err)) = Self::poll_send(io, cx) {
+			return Poll::Ready(Err(err));
+		}
+
+		Poll::Ready(Ok(cnt))
+	}
+
+	/// Like `poll_write`, but copies as many of `bufs` as fit, so they go out with a single send.
+	pub fn poll_write_vectored<T: BufferedWrite>(
+		io: &mut T,
+		cx: &mut Context<'_>,
+		bufs: &[IoSlice<'_>],
+	) -> Poll<io::Result<usize>> {
+		ready!(Self::poll_flush(io, cx))?;
+
+		let this = io.write_buffer();
+		let mut cnt = 0;
+		for buf in bufs {
+			let len = buf.len().min(MAX_BUF - cnt);
+			this.buf.extend_from_slice(&buf[..len]);
+			cnt += len;
+			if cnt == MAX_BUF {
+				break;
+			}
+		}
+		if cnt == 0 {
+			return Poll::Ready(Ok(0));
+		}
+
+		// start sending now, the result is picked up by the next write or flush
+		if let Poll::Ready(Err(err)) = Self::poll_send(io, cx) {
+			return Poll::Ready(Err(err));
+		}
+
+		Poll::Ready(Ok(cnt))
+	}
+
+	pub fn poll_flush<T: BufferedWrite>(io: &mut T, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		while io.write_buffer().pending() {
+			ready!(Self::poll_send(io, cx))?;
+		}
+
+		let this = io.write_buffer();
+		this.buf.clear();
+		this.pos = 0;
 
 		Poll::Ready(Ok(()))
 	}
+
+	/// Get the write op ready for a write that bypasses the buffer, by flushing it first.
+	pub fn poll_prepare<T: BufferedWrite>(
+		io: &mut T,
+		cx: &mut Context<'_>,
+	) -> Poll<io::Result<()>> {
+		ready!(Self::poll_flush(io, cx))?;
+		io.reclaim_op(T::WRITE_OP_ID);
+
+		Poll::Ready(Ok(()))
+	}
+
+	fn pending(&self) -> bool {
+		self.pos < self.buf.len()
+	}
+
+	fn poll_send<T: BufferedWrite>(io: &mut T, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		if !io.write_buffer().sending {
+			io.reclaim_op(T::WRITE_OP_ID);
+			io.write_buffer().sending = true;
+		}
+
+		let this = io.write_buffer();
+		// SAFETY: pos is never larger than the buffer
+		let ptr = unsafe { this.buf.as_ptr().add(this.pos) };
+		let len = this.buf.len() - this.pos;
+
+		// SAFETY: the buffer is handed to the runtime if we are dropped before the op completes
+		let ret = ready!(unsafe { io.poll_write_raw(cx, ptr, len, None) });
+
+		let this = io.write_buffer();
+		this.sending = false;
+		match ret {
+			Ok(0) => {
+				this.buf.clear();
+				this.pos = 0;
+				Poll::Ready(Err(io::ErrorKind::WriteZero.into()))
+			}
+			Ok(cnt) => {
+				this.pos += cnt;
+				Poll::Ready(Ok(()))
+			}
+			Err(err) => {
+				this.buf.clear();
+				this.pos = 0;
+				Poll::Ready(Err(err.into()))
+			}
+		}
+	}
+
+	/// Move the buffer into the runtime if a write is still in flight.
+	///
+	/// The write is left to complete instead of being cancelled, so data that was already
+	/// accepted isn't lost.
+	pub fn detach<T: RawWrite>(&mut self, io: &mut T) {
+		if self.sending {
+			self.sending = false;
+			self.pos = 0;
+			io.detach_op(
+				T::WRITE_OP_ID,
+				OperationCancelData {
+					wake: true,
+					buf: Box::new(std::mem::take(&mut self.buf)),
+					release: None,
+				},
+			);
+		}
+	}
 }
 
-impl<T: Unpin + AsyncWrite + ProtectedOps> AsyncWrite for Copying<T> {
-	fn poll_write(
-		mut self: Pin<&mut Self>,
+/// Types with internal buffers that `Unbuffered` empties before it bypasses them.
+pub(crate) trait Unbuffer: ProtectedOps {
+	/// Flush buffered writes, then take buffered reads like `ReadBuffer::poll_unbuffer`.
+	fn poll_unbuffer(
+		&mut self,
 		cx: &mut Context<'_>,
-		buf: &[u8],
-	) -> Poll<Result<usize, std::io::Error>> {
-		let this = &mut *self;
-		this.write_buf.reserve(buf.len());
-		this.write_buf.extend_from_slice(buf);
+		interrupted: &mut bool,
+	) -> Poll<io::Result<Vec<u8>>>;
+}
 
-		let ret = ready!(Pin::new(this.inner.as_mut().unwrap()).poll_write(cx, &this.write_buf));
+/// Pass caller buffers straight to the kernel instead of copying them into internal buffers.
+#[expect(private_bounds)]
+pub struct Unbuffered<T: Unpin + Unbuffer> {
+	inner: T,
+}
+
+#[expect(private_bounds)]
+impl<T: Unpin + Unbuffer> Unbuffered<T> {
+	/// Disable copying data into internal buffers, along with data already read through
+	/// `AsyncRead` but not consumed yet.
+	///
+	/// Buffered writes are flushed and an in-flight read is cancelled first, like in `into_std`.
+	///
+	/// # Safety
+	/// You must keep any buffers you provide alive for the entire duration of any operations,
+	/// even if the read or write future is dropped before it completes.
+	pub async unsafe fn new(mut val: T) -> io::Result<(Self, Vec<u8>)> {
+		let mut interrupted = false;
+		let leftover = poll_fn(|cx| val.poll_unbuffer(cx, &mut interrupted)).await?;
 
-		this.write_buf.clear();
-		Poll::Ready(ret)
+		Ok((Self { inner: val }, leftover))
 	}
 
-	fn poll_flush(
+	pub fn into_inner(self) -> T {
+		self.inner
+	}
+}
+
+impl<T: Unpin + Unbuffer + RawRead> AsyncRead for Unbuffered<T> {
+	fn poll_read(
 		mut self: Pin<&mut Self>,
 		cx: &mut Context<'_>,
-	) -> Poll<Result<(), std::io::Error>> {
-		Pin::new(self.inner.as_mut().unwrap()).poll_flush(cx)
+		buf: &mut ReadBuf<'_>,
+	) -> Poll<io::Result<()>> {
+		// SAFETY: we send it straight to the kernel and it doesn't de-initialize anything
+		let uninit = unsafe { buf.unfilled_mut() };
+		// SAFETY: enforced by the caller of `Unbuffered::new`
+		let cnt = ready!(unsafe {
+			self.inner
+				.poll_read_raw(cx, uninit.as_mut_ptr().cast::<u8>(), uninit.len(), None)
+		})?;
+		// SAFETY: kernel just initialized these bytes in the read op
+		unsafe { buf.assume_init(cnt) };
+		buf.advance(cnt);
+
+		Poll::Ready(Ok(()))
 	}
+}
 
-	fn poll_shutdown(
+impl<T: Unpin + Unbuffer + RawWrite + AsyncWrite> AsyncWrite for Unbuffered<T> {
+	fn poll_write(
 		mut self: Pin<&mut Self>,
 		cx: &mut Context<'_>,
-	) -> Poll<Result<(), std::io::Error>> {
-		Pin::new(self.inner.as_mut().unwrap()).poll_shutdown(cx)
+		buf: &[u8],
+	) -> Poll<io::Result<usize>> {
+		// SAFETY: enforced by the caller of `Unbuffered::new`
+		unsafe { self.inner.poll_write_raw(cx, buf.as_ptr(), buf.len(), None) }.map_err(Into::into)
 	}
-}
 
-macro_rules! try_cancel {
-	($res:ident, $id:expr, $buf:expr) => {
-		$res.ops.try_cancel(
-			$id,
-			OperationCancelData {
-				wake: false,
-				buf: std::mem::take(&mut $buf),
-			},
-		);
-	};
-}
+	fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.inner).poll_flush(cx)
+	}
 
-impl<T: Unpin + ProtectedOps> Drop for Copying<T> {
-	fn drop(&mut self) {
-		if let Some(mut inner) = self.inner.take() {
-			let res = inner.get_resource();
-			try_cancel!(res, T::READ_OP_ID, self.read_buf);
-			try_cancel!(res, T::WRITE_OP_ID, self.write_buf);
-		}
+	fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.inner).poll_shutdown(cx)
 	}
 }
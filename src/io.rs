@@ -0,0 +1,244 @@
+use std::{
+	io,
+	os::fd::{AsRawFd, FromRawFd, OwnedFd},
+	pin::Pin,
+	sync::Arc,
+	task::{Context, Poll, ready},
+};
+
+use tokio::io::ReadBuf;
+
+use crate::{
+	compat::{ReadBuffer, WriteBuffer},
+	rt::operation::{OperationCancelData, Splice},
+};
+
+/// Most bytes moved by a single splice, the default capacity of a pipe.
+const CHUNK: u32 = 64 * 1024;
+
+struct Pipe {
+	read: OwnedFd,
+	write: OwnedFd,
+}
+
+impl Pipe {
+	fn new() -> io::Result<Self> {
+		let mut fds = [0; 2];
+		// SAFETY: pipe2 writes two fds into the array
+		if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+			return Err(io::Error::last_os_error());
+		}
+
+		// SAFETY: the fds were just created and nobody else owns them
+		Ok(unsafe {
+			Self {
+				read: OwnedFd::from_raw_fd(fds[0]),
+				write: OwnedFd::from_raw_fd(fds[1]),
+			}
+		})
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Step {
+	/// Pass on data `AsyncRead` already buffered before splicing.
+	Drain,
+	Flush,
+	SpliceIn,
+	Readable,
+	SpliceOut,
+	Writable,
+	Shutdown,
+	Done,
+}
+
+/// Copies one direction through its own pipe.
+struct Direction {
+	// shared with the runtime while an op on it is cancelled, so the kernel never sees it closed
+	pipe: Arc<Pipe>,
+	step: Step,
+	carry: Vec<u8>,
+	carry_pos: usize,
+	// spliced into the pipe but not out yet
+	in_pipe: u32,
+	total: u64,
+}
+
+impl Direction {
+	fn new() -> io::Result<Self> {
+		Ok(Self {
+			pipe: Arc::new(Pipe::new()?),
+			step: Step::Drain,
+			carry: Vec::new(),
+			carry_pos: 0,
+			in_pipe: 0,
+			total: 0,
+		})
+	}
+
+	fn poll<R: Splice, W: Splice>(
+		&mut self,
+		cx: &mut Context<'_>,
+		from: &mut R,
+		to: &mut W,
+	) -> Poll<io::Result<u64>> {
+		loop {
+			match self.step {
+				Step::Drain => {
+					if self.carry_pos < self.carry.len() {
+						let cnt = ready!(WriteBuffer::poll_write(
+							to,
+							cx,
+							&self.carry[self.carry_pos..]
+						))?;
+						self.carry_pos += cnt;
+						self.total += cnt as u64;
+						continue;
+					}
+
+					// a read still in flight would take the completion of the first splice
+					ready!(ReadBuffer::poll_prepare(from, cx))?;
+					if from.read_buffer().is_empty() {
+						self.carry = Vec::new();
+						self.step = Step::Flush;
+					} else {
+						self.carry.resize(CHUNK as usize, 0);
+						let mut buf = ReadBuf::new(&mut self.carry);
+						ready!(ReadBuffer::poll_read(from, cx, &mut buf))?;
+						let cnt = buf.filled().len();
+
+						self.carry.truncate(cnt);
+						self.carry_pos = 0;
+					}
+				}
+				Step::Flush => {
+					ready!(WriteBuffer::poll_prepare(to, cx))?;
+					self.step = Step::SpliceIn;
+				}
+				Step::SpliceIn => {
+					let pipe = self.pipe.write.as_raw_fd();
+					// SAFETY: the pipe is handed to the runtime if we are dropped before the op
+					// completes
+					match ready!(unsafe { from.poll_splice_in(cx, pipe, CHUNK) })
+						.map_err(io::Error::from)
+					{
+						Ok(0) => self.step = Step::Shutdown,
+						Ok(cnt) => {
+							self.in_pipe = u32::try_from(cnt).expect("spliced more than asked for");
+							self.step = Step::SpliceOut;
+						}
+						// the socket is nonblocking, so the kernel doesn't wait for data itself
+						Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+							self.step = Step::Readable;
+						}
+						Err(err) => return Poll::Ready(Err(err)),
+					}
+				}
+				Step::Readable => {
+					ready!(from.poll_readable(cx))?;
+					self.step = Step::SpliceIn;
+				}
+				Step::SpliceOut => {
+					let pipe = self.pipe.read.as_raw_fd();
+					// SAFETY: the pipe is handed to the runtime if we are dropped before the op
+					// completes
+					match ready!(unsafe { to.poll_splice_out(cx, pipe, self.in_pipe) })
+						.map_err(io::Error::from)
+					{
+						Ok(0) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+						Ok(cnt) => {
+							self.in_pipe -=
+								u32::try_from(cnt).expect("spliced more than asked for");
+							self.total += cnt as u64;
+							if self.in_pipe == 0 {
+								self.step = Step::SpliceIn;
+							}
+						}
+						Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+							self.step = Step::Writable;
+						}
+						Err(err) => return Poll::Ready(Err(err)),
+					}
+				}
+				Step::Writable => {
+					ready!(to.poll_writable(cx))?;
+					self.step = Step::SpliceOut;
+				}
+				Step::Shutdown => {
+					ready!(WriteBuffer::poll_flush(to, cx))?;
+					ready!(to.poll_shutdown_write(cx))?;
+					self.step = Step::Done;
+				}
+				Step::Done => return Poll::Ready(Ok(self.total)),
+			}
+		}
+	}
+
+	/// Cancel the op of ours that might be in flight, keeping the pipe open until it completes.
+	fn cancel<R: Splice, W: Splice>(&mut self, from: &mut R, to: &mut W) {
+		let data = || OperationCancelData {
+			wake: true,
+			buf: Box::new(self.pipe.clone()),
+			release: None,
+		};
+
+		// the other steps use the stream's own buffers, which keep track of their ops
+		match self.step {
+			Step::SpliceIn | Step::Readable => from.cancel_op(R::READ_OP_ID, data()),
+			Step::SpliceOut | Step::Writable => to.cancel_op(W::WRITE_OP_ID, data()),
+			Step::Drain | Step::Flush | Step::Shutdown | Step::Done => {}
+		}
+	}
+}
+
+struct SpliceBidirectional<'a, A: Splice, B: Splice> {
+	a: &'a mut A,
+	b: &'a mut B,
+	a_to_b: Direction,
+	b_to_a: Direction,
+}
+
+impl<A: Splice, B: Splice> Future for SpliceBidirectional<'_, A, B> {
+	type Output = io::Result<(u64, u64)>;
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		let this = &mut *self;
+
+		let a_to_b = this.a_to_b.poll(cx, this.a, this.b)?;
+		let b_to_a = this.b_to_a.poll(cx, this.b, this.a)?;
+
+		Poll::Ready(Ok((ready!(a_to_b), ready!(b_to_a))))
+	}
+}
+
+impl<A: Splice, B: Splice> Drop for SpliceBidirectional<'_, A, B> {
+	fn drop(&mut self) {
+		self.a_to_b.cancel(self.a, self.b);
+		self.b_to_a.cancel(self.b, self.a);
+	}
+}
+
+/// Copy data between two streams in both directions until both reach EOF, returning the number
+/// of bytes copied from `a` to `b` and from `b` to `a`.
+///
+/// The bytes are spliced through a pipe per direction, so they never enter userspace. Once one
+/// side reaches EOF, the write direction of the other side is shut down, while the opposite
+/// direction keeps going. Data already buffered by `AsyncRead` or `AsyncWrite` is passed on
+/// first.
+///
+/// If this future is dropped early, the ops in flight are cancelled and the runtime keeps their
+/// pipes open until the kernel is done with them. Data that was already spliced into a pipe is
+/// lost.
+#[expect(private_bounds)]
+pub async fn splice_bidirectional<A: Splice, B: Splice>(
+	a: &mut A,
+	b: &mut B,
+) -> io::Result<(u64, u64)> {
+	SpliceBidirectional {
+		a,
+		b,
+		a_to_b: Direction::new()?,
+		b_to_a: Direction::new()?,
+	}
+	.await
+}
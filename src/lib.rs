@@ -1,4 +1,6 @@
+pub mod buf;
 pub mod compat;
+pub mod io;
 pub mod net;
 pub mod nop;
 pub mod rt;
@@ -15,10 +17,27 @@ pub enum Error {
 	TooManyResources,
 	#[error("Buffer too large")]
 	BufferTooLarge,
+	#[error("Too many entries to submit at once")]
+	TooManyEntries,
+	#[error("Too many operations in chain")]
+	ChainTooLong,
+	#[error("No provided buffer ring registered")]
+	NoBufferRing,
 	#[error("Resource is closing")]
 	ResourceClosing,
 	#[error("Runtime is dead or unreachable")]
 	NoRuntime,
+	#[error("Data is still buffered by AsyncRead")]
+	ReadBuffered,
+}
+
+impl From<Error> for std::io::Error {
+	fn from(value: Error) -> Self {
+		match value {
+			Error::Io(err) => err,
+			other => std::io::Error::other(other),
+		}
+	}
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,287 @@
+use std::{
+	ffi::OsStr,
+	io,
+	net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+	os::{linux::net::SocketAddrExt, unix::ffi::OsStrExt},
+};
+
+use super::sockopt::socklen;
+
+/// Socket address storage the kernel reads from or writes into.
+///
+/// Boxed and owned by the op, so it stays valid if the op is cancelled.
+pub(crate) struct SockAddr {
+	storage: libc::sockaddr_storage,
+	len: libc::socklen_t,
+}
+
+impl SockAddr {
+	#[expect(clippy::cast_possible_truncation)]
+	const STORAGE_LEN: libc::socklen_t = size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+
+	/// Empty storage for the kernel to write an address into.
+	pub fn new() -> Box<Self> {
+		Box::new(Self {
+			// SAFETY: all zeroes is a valid sockaddr_storage
+			storage: unsafe { std::mem::zeroed() },
+			len: Self::STORAGE_LEN,
+		})
+	}
+
+	// address families are small constants
+	#[expect(clippy::cast_possible_truncation)]
+	pub fn from_std(addr: SocketAddr) -> Box<Self> {
+		let mut this = Self::new();
+		let storage = (&raw mut this.storage).cast::<u8>();
+
+		match addr {
+			SocketAddr::V4(addr) => {
+				let sin = libc::sockaddr_in {
+					sin_family: libc::AF_INET as libc::sa_family_t,
+					sin_port: addr.port().to_be(),
+					sin_addr: libc::in_addr {
+						s_addr: u32::from(*addr.ip()).to_be(),
+					},
+					sin_zero: [0; 8],
+				};
+				// SAFETY: sockaddr_storage is large enough for any address
+				unsafe { storage.cast::<libc::sockaddr_in>().write_unaligned(sin) };
+				this.len = socklen(size_of::<libc::sockaddr_in>());
+			}
+			SocketAddr::V6(addr) => {
+				let sin6 = libc::sockaddr_in6 {
+					sin6_family: libc::AF_INET6 as libc::sa_family_t,
+					sin6_port: addr.port().to_be(),
+					sin6_flowinfo: addr.flowinfo(),
+					sin6_addr: libc::in6_addr {
+						s6_addr: addr.ip().octets(),
+					},
+					sin6_scope_id: addr.scope_id(),
+				};
+				// SAFETY: sockaddr_storage is large enough for any address
+				unsafe { storage.cast::<libc::sockaddr_in6>().write_unaligned(sin6) };
+				this.len = socklen(size_of::<libc::sockaddr_in6>());
+			}
+		}
+
+		this
+	}
+
+	/// Address of a Unix socket, either a path or a name in the abstract namespace.
+	// address families are small constants
+	#[expect(clippy::cast_possible_truncation)]
+	pub fn from_unix(addr: &std::os::unix::net::SocketAddr) -> io::Result<Box<Self>> {
+		let invalid = |msg| io::Error::new(io::ErrorKind::InvalidInput, msg);
+
+		// pathnames are nul terminated, abstract names start with a nul instead
+		let (name, prefix, suffix) = if let Some(path) = addr.as_pathname() {
+			(path.as_os_str().as_bytes(), 0, 1)
+		} else if let Some(name) = addr.as_abstract_name() {
+			(name, 1, 0)
+		} else {
+			return Err(invalid("unnamed unix address"));
+		};
+
+		let mut this = Self::new();
+		// SAFETY: sockaddr_storage is large enough and aligned for any address
+		let sun = unsafe { &mut *(&raw mut this.storage).cast::<libc::sockaddr_un>() };
+		sun.sun_family = libc::AF_UNIX as libc::sa_family_t;
+
+		let path = &mut sun.sun_path[prefix..];
+		if name.len() + suffix > path.len() {
+			return Err(invalid("unix address too long"));
+		}
+		for (dst, src) in path.iter_mut().zip(name) {
+			*dst = libc::c_char::from_ne_bytes([*src]);
+		}
+
+		let len = std::mem::offset_of!(libc::sockaddr_un, sun_path) + prefix + name.len() + suffix;
+		this.len = socklen(len);
+
+		Ok(this)
+	}
+
+	/// Make room for the kernel to write a new address.
+	pub fn reset(&mut self) {
+		self.len = Self::STORAGE_LEN;
+	}
+
+	pub fn as_ptr(&self) -> *const libc::sockaddr {
+		(&raw const self.storage).cast()
+	}
+
+	pub fn as_mut_ptr(&mut self) -> *mut libc::sockaddr {
+		(&raw mut self.storage).cast()
+	}
+
+	pub fn len(&self) -> libc::socklen_t {
+		self.len
+	}
+
+	pub fn len_mut(&mut self) -> *mut libc::socklen_t {
+		&raw mut self.len
+	}
+
+	/// Length the kernel reported for an address it wrote.
+	pub fn set_len(&mut self, len: libc::socklen_t) {
+		self.len = len.min(Self::STORAGE_LEN);
+	}
+
+	/// Unix socket address, `None` if the socket isn't bound to one.
+	pub fn to_unix(&self) -> io::Result<Option<std::os::unix::net::SocketAddr>> {
+		// unnamed senders don't even get a family written
+		let offset = std::mem::offset_of!(libc::sockaddr_un, sun_path);
+		let len = self.len as usize;
+		if len <= offset {
+			return Ok(None);
+		}
+		if i32::from(self.storage.ss_family) != libc::AF_UNIX {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"not a unix address",
+			));
+		}
+
+		// SAFETY: the kernel wrote len bytes, which fit in the storage
+		let name = unsafe {
+			std::slice::from_raw_parts(
+				(&raw const self.storage).cast::<u8>().add(offset),
+				len - offset,
+			)
+		};
+		let addr = if let Some(name) = name.strip_prefix(&[0]) {
+			std::os::unix::net::SocketAddr::from_abstract_name(name)?
+		} else {
+			let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+			std::os::unix::net::SocketAddr::from_pathname(OsStr::from_bytes(&name[..end]))?
+		};
+		Ok(Some(addr))
+	}
+
+	pub fn to_std(&self) -> io::Result<SocketAddr> {
+		let storage = (&raw const self.storage).cast::<u8>();
+
+		match i32::from(self.storage.ss_family) {
+			libc::AF_INET => {
+				// SAFETY: the family says this is a sockaddr_in
+				let sin = unsafe { storage.cast::<libc::sockaddr_in>().read_unaligned() };
+				Ok(SocketAddr::V4(SocketAddrV4::new(
+					Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)),
+					u16::from_be(sin.sin_port),
+				)))
+			}
+			libc::AF_INET6 => {
+				// SAFETY: the family says this is a sockaddr_in6
+				let sin6 = unsafe { storage.cast::<libc::sockaddr_in6>().read_unaligned() };
+				Ok(SocketAddr::V6(SocketAddrV6::new(
+					Ipv6Addr::from(sin6.sin6_addr.s6_addr),
+					u16::from_be(sin6.sin6_port),
+					sin6.sin6_flowinfo,
+					sin6.sin6_scope_id,
+				)))
+			}
+			_ => Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"unsupported address family",
+			)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::os::unix::net::{SocketAddr as UnixAddr, UnixDatagram};
+
+	use super::*;
+
+	const PATH_OFFSET: usize = std::mem::offset_of!(libc::sockaddr_un, sun_path);
+
+	fn unix_round_trip(addr: &UnixAddr) -> (usize, UnixAddr) {
+		let sock = SockAddr::from_unix(addr).unwrap();
+		(sock.len() as usize, sock.to_unix().unwrap().unwrap())
+	}
+
+	#[test]
+	fn unix_pathname() {
+		let (len, addr) = unix_round_trip(&UnixAddr::from_pathname("/tmp/sock").unwrap());
+		assert_eq!(len, PATH_OFFSET + "/tmp/sock".len() + 1);
+		assert_eq!(addr.as_pathname(), Some("/tmp/sock".as_ref()));
+	}
+
+	#[test]
+	fn unix_abstract() {
+		let (len, addr) = unix_round_trip(&UnixAddr::from_abstract_name(b"name\0x").unwrap());
+		assert_eq!(len, PATH_OFFSET + 1 + 6);
+		assert_eq!(addr.as_abstract_name(), Some(&b"name\0x"[..]));
+		assert!(addr.as_pathname().is_none());
+	}
+
+	#[test]
+	fn unix_abstract_longest() {
+		let name = [b'a'; 107];
+		let (_, addr) = unix_round_trip(&UnixAddr::from_abstract_name(name).unwrap());
+		assert_eq!(addr.as_abstract_name(), Some(&name[..]));
+	}
+
+	#[test]
+	fn unix_unnamed() {
+		let unnamed = UnixDatagram::unbound().unwrap().local_addr().unwrap();
+		let Err(err) = SockAddr::from_unix(&unnamed) else {
+			panic!("unnamed address was accepted");
+		};
+		assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+		// what the kernel reports for an unbound sender
+		let mut sock = SockAddr::new();
+		sock.set_len(0);
+		assert!(sock.to_unix().unwrap().is_none());
+		sock.set_len(PATH_OFFSET.try_into().unwrap());
+		assert!(sock.to_unix().unwrap().is_none());
+	}
+
+	#[test]
+	fn unix_wrong_family() {
+		let sock = SockAddr::from_std("127.0.0.1:80".parse().unwrap());
+		let err = sock.to_unix().unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+	}
+
+	#[test]
+	fn std_v4() {
+		let addr: SocketAddr = "192.0.2.1:8080".parse().unwrap();
+		let sock = SockAddr::from_std(addr);
+		assert_eq!(sock.len() as usize, size_of::<libc::sockaddr_in>());
+
+		// SAFETY: from_std wrote a sockaddr_in
+		let sin = unsafe { sock.as_ptr().cast::<libc::sockaddr_in>().read_unaligned() };
+		assert_eq!(sin.sin_port, 8080u16.to_be());
+		assert_eq!(sin.sin_addr.s_addr.to_ne_bytes(), [192, 0, 2, 1]);
+
+		assert_eq!(sock.to_std().unwrap(), addr);
+	}
+
+	#[test]
+	fn std_v6() {
+		let addr = SocketAddr::V6(SocketAddrV6::new(
+			"2001:db8::1".parse().unwrap(),
+			443,
+			0x12345,
+			7,
+		));
+		let sock = SockAddr::from_std(addr);
+		assert_eq!(sock.len() as usize, size_of::<libc::sockaddr_in6>());
+		assert_eq!(sock.to_std().unwrap(), addr);
+	}
+
+	#[test]
+	fn std_unsupported_family() {
+		let err = SockAddr::new().to_std().unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+		let unix = SockAddr::from_unix(&UnixAddr::from_pathname("/tmp/sock").unwrap()).unwrap();
+		assert_eq!(
+			unix.to_std().unwrap_err().kind(),
+			io::ErrorKind::InvalidInput
+		);
+	}
+}
@@ -0,0 +1,41 @@
+use std::ops::{BitOr, BitOrAssign};
+
+/// Flags passed to `recv`, for example to peek at data without consuming it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct RecvFlags(i32);
+
+impl RecvFlags {
+	/// Return data without removing it from the receive queue (`MSG_PEEK`).
+	pub const PEEK: Self = Self(libc::MSG_PEEK);
+	/// Wait until the whole buffer is filled, unless the peer shuts down or an error occurs
+	/// (`MSG_WAITALL`).
+	pub const WAITALL: Self = Self(libc::MSG_WAITALL);
+	/// Receive out-of-band data (`MSG_OOB`).
+	pub const OOB: Self = Self(libc::MSG_OOB);
+
+	pub const fn empty() -> Self {
+		Self(0)
+	}
+
+	pub const fn bits(self) -> i32 {
+		self.0
+	}
+
+	pub const fn contains(self, other: Self) -> bool {
+		self.0 & other.0 == other.0
+	}
+}
+
+impl BitOr for RecvFlags {
+	type Output = Self;
+
+	fn bitor(self, rhs: Self) -> Self {
+		Self(self.0 | rhs.0)
+	}
+}
+
+impl BitOrAssign for RecvFlags {
+	fn bitor_assign(&mut self, rhs: Self) {
+		self.0 |= rhs.0;
+	}
+}
@@ -1,3 +1,23 @@
+pub(crate) mod addr;
+mod flags;
+pub(crate) mod msg;
+pub(crate) mod sockopt;
+pub(crate) mod stream;
 pub mod tcp;
+mod udp;
+pub mod unix;
 
-pub use tcp::TcpStream;
+pub use flags::RecvFlags;
+pub use tcp::{TcpListener, TcpSocket, TcpStream};
+pub use udp::UdpSocket;
+pub use unix::{UnixDatagram, UnixListener, UnixStream};
+
+use std::os::fd::{FromRawFd, OwnedFd};
+
+/// Close an fd returned by an op nobody will see the completion of.
+pub(crate) fn close_fd(fd: i32) {
+	if fd >= 0 {
+		// SAFETY: the fd was just created and nobody else knows about it
+		drop(unsafe { OwnedFd::from_raw_fd(fd) });
+	}
+}
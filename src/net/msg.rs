@@ -0,0 +1,574 @@
+use std::{
+	io,
+	net::SocketAddr,
+	os::fd::{AsRawFd, FromRawFd, OwnedFd},
+	pin::Pin,
+	task::{Context, Poll, ready},
+};
+
+use crate::{
+	Result,
+	buf::{IoBuf, IoBufMut},
+	rt::operation::{OperationCancelData, ProtectedOps},
+};
+
+use super::{addr::SockAddr, sockopt::socklen};
+
+/// msghdr together with the iovec, address and control messages it points to.
+///
+/// Boxed and owned by the op, so it stays valid if the op is cancelled.
+pub(crate) struct Msg {
+	hdr: libc::msghdr,
+	iov: libc::iovec,
+	addr: Box<SockAddr>,
+	// u64s so every cmsghdr in it is aligned
+	control: Vec<u64>,
+	// sent with SCM_RIGHTS, kept open until the kernel took its own references
+	fds: Vec<OwnedFd>,
+	// how many fds the caller wants, if the kernel may install some into the control buffer
+	recv_fds: Option<usize>,
+}
+
+// SAFETY: the pointers only point into the box itself and the buffer of the op that owns it
+unsafe impl Send for Msg {}
+
+impl Msg {
+	fn new(ptr: *mut u8, len: usize, addr: Option<Box<SockAddr>>) -> Box<Self> {
+		let has_addr = addr.is_some();
+		let mut this = Box::new(Self {
+			// SAFETY: all zeroes is a valid msghdr
+			hdr: unsafe { std::mem::zeroed() },
+			iov: libc::iovec {
+				iov_base: ptr.cast(),
+				iov_len: len,
+			},
+			addr: addr.unwrap_or_else(SockAddr::new),
+			control: Vec::new(),
+			fds: Vec::new(),
+			recv_fds: None,
+		});
+
+		this.hdr.msg_iov = &raw mut this.iov;
+		this.hdr.msg_iovlen = 1;
+		if has_addr {
+			this.hdr.msg_name = this.addr.as_mut_ptr().cast();
+			this.hdr.msg_namelen = this.addr.len();
+		}
+
+		this
+	}
+
+	/// Header for sending `len` bytes at `ptr`, to `addr` if the socket isn't connected.
+	pub fn send(ptr: *const u8, len: usize, addr: Option<Box<SockAddr>>) -> Box<Self> {
+		Self::new(ptr.cast_mut(), len, addr)
+	}
+
+	/// Header for receiving up to `len` bytes into `ptr` along with the sender's address.
+	pub fn recv(ptr: *mut u8, len: usize) -> Box<Self> {
+		Self::new(ptr, len, Some(SockAddr::new()))
+	}
+
+	pub fn as_ptr(&self) -> *const libc::msghdr {
+		&raw const self.hdr
+	}
+
+	pub fn as_mut_ptr(&mut self) -> *mut libc::msghdr {
+		&raw mut self.hdr
+	}
+
+	/// Address the kernel wrote into a received header.
+	pub fn addr(&self) -> io::Result<SocketAddr> {
+		self.addr.to_std()
+	}
+
+	/// Unix socket address the kernel wrote into a received header, `None` if the sender isn't
+	/// bound.
+	pub fn unix_addr(&mut self) -> io::Result<Option<std::os::unix::net::SocketAddr>> {
+		self.addr.set_len(self.hdr.msg_namelen);
+		self.addr.to_unix()
+	}
+
+	fn set_control_len(&mut self, len: usize) {
+		self.control.resize(len.div_ceil(size_of::<u64>()), 0);
+		self.hdr.msg_control = self.control.as_mut_ptr().cast();
+		self.hdr.msg_controllen = len;
+	}
+
+	/// Append a control message to send.
+	pub fn push_cmsg(&mut self, level: i32, ty: i32, data: &[u8]) {
+		let offset = self.hdr.msg_controllen;
+		self.set_control_len(offset + cmsg_space(data.len()));
+
+		// SAFETY: the control buffer was just grown to fit the message at `offset`, which is
+		// aligned since every message before it takes up a multiple of the alignment
+		unsafe {
+			let cmsg = self
+				.control
+				.as_mut_ptr()
+				.byte_add(offset)
+				.cast::<libc::cmsghdr>();
+			// the aligned header followed by the data, same as CMSG_LEN
+			(*cmsg).cmsg_len = cmsg_space(0) + data.len();
+			(*cmsg).cmsg_level = level;
+			(*cmsg).cmsg_type = ty;
+			std::ptr::copy_nonoverlapping(data.as_ptr(), libc::CMSG_DATA(cmsg), data.len());
+		}
+	}
+
+	/// Pass `fds` to the receiver with `SCM_RIGHTS`.
+	pub fn push_fds(&mut self, fds: Vec<OwnedFd>) {
+		let data: Vec<u8> = fds
+			.iter()
+			.flat_map(|fd| fd.as_raw_fd().to_ne_bytes())
+			.collect();
+		self.push_cmsg(libc::SOL_SOCKET, libc::SCM_RIGHTS, &data);
+		self.fds.extend(fds);
+	}
+
+	/// Pass `cred` to the receiver with `SCM_CREDENTIALS`.
+	pub fn push_cred(&mut self, cred: libc::ucred) {
+		// SAFETY: ucred is three integers without padding
+		let data = unsafe {
+			std::slice::from_raw_parts((&raw const cred).cast::<u8>(), size_of::<libc::ucred>())
+		};
+		self.push_cmsg(libc::SOL_SOCKET, libc::SCM_CREDENTIALS, data);
+	}
+
+	/// Make room for the kernel to write another `len` bytes of control messages.
+	pub fn reserve_control(&mut self, len: usize) {
+		self.set_control_len(self.hdr.msg_controllen + len);
+	}
+
+	/// Make room for up to `max` fds received with `SCM_RIGHTS`.
+	pub fn reserve_fds(&mut self, max: usize) {
+		self.reserve_control(cmsg_space(max * size_of::<libc::c_int>()));
+		self.recv_fds = Some(max);
+	}
+
+	/// Take ownership of the fds the kernel installed for `SCM_RIGHTS` messages. Any past the
+	/// maximum that fit in the padding of the control buffer are closed.
+	pub fn take_fds(&mut self) -> Vec<OwnedFd> {
+		let Some(max) = self.recv_fds.take() else {
+			return Vec::new();
+		};
+
+		let mut fds: Vec<_> = self
+			.cmsgs()
+			.filter(|(level, ty, _)| *level == libc::SOL_SOCKET && *ty == libc::SCM_RIGHTS)
+			.flat_map(|(_, _, data)| data.chunks_exact(size_of::<libc::c_int>()))
+			// SAFETY: the kernel just installed these fds for us
+			.map(|fd| unsafe {
+				OwnedFd::from_raw_fd(libc::c_int::from_ne_bytes(fd.try_into().unwrap()))
+			})
+			.collect();
+		fds.truncate(max);
+		fds
+	}
+
+	/// Credentials from an `SCM_CREDENTIALS` message, sent along when `SO_PASSCRED` is set.
+	pub fn cred(&self) -> Option<libc::ucred> {
+		self.cmsgs()
+			.find(|(level, ty, _)| *level == libc::SOL_SOCKET && *ty == libc::SCM_CREDENTIALS)
+			.filter(|(_, _, data)| data.len() >= size_of::<libc::ucred>())
+			// SAFETY: the message holds a ucred
+			.map(|(_, _, data)| unsafe { data.as_ptr().cast::<libc::ucred>().read_unaligned() })
+	}
+
+	/// Control messages the kernel wrote into a received header, as level, type and data.
+	pub fn cmsgs(&self) -> impl Iterator<Item = (i32, i32, &[u8])> {
+		// SAFETY: the header's control buffer and length are either ours or were set by the kernel
+		let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&raw const self.hdr) };
+		std::iter::from_fn(move || {
+			if cmsg.is_null() {
+				return None;
+			}
+			// SAFETY: CMSG_FIRSTHDR and CMSG_NXTHDR only return headers that fit in the buffer,
+			// and the kernel never writes a length past its end
+			unsafe {
+				// a control buffer the kernel never wrote to is all zeroes
+				if (*cmsg).cmsg_len < cmsg_space(0) {
+					return None;
+				}
+				let data = libc::CMSG_DATA(cmsg);
+				let len = (*cmsg).cmsg_len - cmsg_space(0);
+				let ret = (
+					(*cmsg).cmsg_level,
+					(*cmsg).cmsg_type,
+					std::slice::from_raw_parts(data.cast_const(), len),
+				);
+				cmsg = libc::CMSG_NXTHDR(&raw const self.hdr, cmsg);
+				Some(ret)
+			}
+		})
+	}
+}
+
+impl Drop for Msg {
+	fn drop(&mut self) {
+		// close fds of a completed recv nobody took, the buffer is all zeroes otherwise
+		drop(self.take_fds());
+	}
+}
+
+/// Room a control message with `len` bytes of data takes up in the control buffer.
+pub(crate) fn cmsg_space(len: usize) -> usize {
+	// SAFETY: CMSG_SPACE only does arithmetic
+	let space = unsafe { libc::CMSG_SPACE(socklen(len)) };
+	space as usize
+}
+
+pub(crate) trait MsgIo: ProtectedOps {
+	/// Send the message `msg` describes.
+	///
+	/// SAFETY: make sure the msghdr and everything it points to stays alive until the op completes
+	unsafe fn poll_send_msg(
+		&mut self,
+		cx: &mut Context<'_>,
+		msg: *const libc::msghdr,
+	) -> Poll<Result<u32>>;
+
+	/// Receive into the buffers `msg` describes, with `MSG_*` `flags`.
+	///
+	/// SAFETY: make sure the msghdr and everything it points to stays alive until the op completes
+	unsafe fn poll_recv_msg(
+		&mut self,
+		cx: &mut Context<'_>,
+		msg: *mut libc::msghdr,
+		flags: u32,
+	) -> Poll<Result<u32>>;
+
+	/// Get the send op ready for a message, once whatever has to go out first did.
+	fn poll_prepare_send(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		self.reclaim_op(Self::WRITE_OP_ID);
+		Poll::Ready(Ok(()))
+	}
+
+	/// Get the recv op ready for a message.
+	fn poll_prepare_recv(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		self.reclaim_op(Self::READ_OP_ID);
+		Poll::Ready(Ok(()))
+	}
+}
+
+pub(crate) trait MsgZcIo: MsgIo {
+	/// Send the message `msg` describes without copying the data into the kernel, completing
+	/// once the kernel is done with it.
+	///
+	/// SAFETY: make sure the msghdr and everything it points to stays alive until the op completes
+	unsafe fn poll_send_msg_zc(
+		&mut self,
+		cx: &mut Context<'_>,
+		msg: *const libc::msghdr,
+	) -> Poll<Result<u32>>;
+}
+
+type PollSend<T> = unsafe fn(&mut T, &mut Context<'_>, *const libc::msghdr) -> Poll<Result<u32>>;
+
+pub(crate) struct SendMsg<'a, T: MsgIo, B: IoBuf> {
+	io: &'a mut T,
+	data: Option<(B, Box<Msg>)>,
+	poll: PollSend<T>,
+	submitted: bool,
+}
+
+impl<'a, T: MsgIo, B: IoBuf> SendMsg<'a, T, B> {
+	pub fn new(io: &'a mut T, buf: B, addr: Option<Box<SockAddr>>) -> Self {
+		let msg = Msg::send(buf.stable_ptr(), buf.bytes_init(), addr);
+		Self {
+			io,
+			data: Some((buf, msg)),
+			poll: T::poll_send_msg,
+			submitted: false,
+		}
+	}
+
+	/// Send a control message along with the data.
+	pub fn cmsg(mut self, level: i32, ty: i32, data: &[u8]) -> Self {
+		if let Some((_, msg)) = &mut self.data {
+			msg.push_cmsg(level, ty, data);
+		}
+		self
+	}
+
+	/// Pass `fds` to the receiver, they are closed once the send completes.
+	pub fn fds(mut self, fds: Vec<OwnedFd>) -> Self {
+		if let Some((_, msg)) = &mut self.data {
+			msg.push_fds(fds);
+		}
+		self
+	}
+
+	/// Pass `cred` to the receiver.
+	pub fn cred(mut self, cred: libc::ucred) -> Self {
+		if let Some((_, msg)) = &mut self.data {
+			msg.push_cred(cred);
+		}
+		self
+	}
+}
+
+impl<T: MsgZcIo, B: IoBuf> SendMsg<'_, T, B> {
+	/// Send without copying the data into the kernel (`SendMsgZc`), completing once the kernel
+	/// releases the buffer rather than once the data is queued.
+	pub fn zerocopy(mut self) -> Self {
+		self.poll = T::poll_send_msg_zc;
+		self
+	}
+}
+
+impl<T: MsgIo, B: IoBuf> Future for SendMsg<'_, T, B> {
+	type Output = (io::Result<usize>, B);
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		let this = &mut *self;
+		let (_, msg) = this.data.as_ref().expect("polled after completion");
+
+		if !this.submitted
+			&& let Err(err) = ready!(this.io.poll_prepare_send(cx))
+		{
+			let (buf, _) = this.data.take().unwrap();
+			return Poll::Ready((Err(err), buf));
+		}
+
+		this.submitted = true;
+		// SAFETY: the buffer and header are handed to the runtime if we are dropped before the op
+		// completes
+		let ret = ready!(unsafe { (this.poll)(this.io, cx, msg.as_ptr()) });
+
+		let (buf, _) = this.data.take().unwrap();
+		Poll::Ready((ret.map(|cnt| cnt as usize).map_err(Into::into), buf))
+	}
+}
+
+impl<T: MsgIo, B: IoBuf> Drop for SendMsg<'_, T, B> {
+	fn drop(&mut self) {
+		if self.submitted
+			&& let Some(data) = self.data.take()
+		{
+			self.io.cancel_op(
+				T::WRITE_OP_ID,
+				OperationCancelData {
+					wake: true,
+					buf: Box::new(data),
+					release: None,
+				},
+			);
+		}
+	}
+}
+
+pub(crate) struct RecvMsg<'a, T: MsgIo, B: IoBufMut> {
+	io: &'a mut T,
+	data: Option<(B, Box<Msg>)>,
+	flags: u32,
+	submitted: bool,
+}
+
+impl<'a, T: MsgIo, B: IoBufMut> RecvMsg<'a, T, B> {
+	pub fn new(io: &'a mut T, mut buf: B) -> Self {
+		let msg = Msg::recv(buf.stable_mut_ptr(), buf.bytes_total());
+		Self {
+			io,
+			data: Some((buf, msg)),
+			flags: 0,
+			submitted: false,
+		}
+	}
+
+	/// Make room for `len` bytes of control messages.
+	pub fn control(mut self, len: usize) -> Self {
+		if let Some((_, msg)) = &mut self.data {
+			msg.reserve_control(len);
+		}
+		self
+	}
+
+	/// Make room for up to `max` fds passed with `SCM_RIGHTS`, which are received close-on-exec.
+	pub fn fds(mut self, max: usize) -> Self {
+		if let Some((_, msg)) = &mut self.data {
+			msg.reserve_fds(max);
+		}
+		self.flags |= libc::MSG_CMSG_CLOEXEC.cast_unsigned();
+		self
+	}
+}
+
+impl<T: MsgIo, B: IoBufMut> Future for RecvMsg<'_, T, B> {
+	type Output = (io::Result<(usize, Box<Msg>)>, B);
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		let this = &mut *self;
+		let (_, msg) = this.data.as_mut().expect("polled after completion");
+
+		if !this.submitted
+			&& let Err(err) = ready!(this.io.poll_prepare_recv(cx))
+		{
+			let (buf, _) = this.data.take().unwrap();
+			return Poll::Ready((Err(err), buf));
+		}
+
+		this.submitted = true;
+		// SAFETY: the buffer and header are handed to the runtime if we are dropped before the op
+		// completes
+		let ret = ready!(unsafe { this.io.poll_recv_msg(cx, msg.as_mut_ptr(), this.flags) });
+
+		let (mut buf, msg) = this.data.take().unwrap();
+		let ret = match ret {
+			Ok(cnt) => {
+				// SAFETY: kernel just initialized these bytes in the recv op
+				unsafe { buf.set_init(cnt as usize) };
+				Ok((cnt as usize, msg))
+			}
+			Err(err) => Err(err.into()),
+		};
+
+		Poll::Ready((ret, buf))
+	}
+}
+
+impl<T: MsgIo, B: IoBufMut> Drop for RecvMsg<'_, T, B> {
+	fn drop(&mut self) {
+		if self.submitted
+			&& let Some(data) = self.data.take()
+		{
+			self.io.cancel_op(
+				T::READ_OP_ID,
+				OperationCancelData {
+					wake: true,
+					buf: Box::new(data),
+					release: None,
+				},
+			);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::os::fd::{IntoRawFd, RawFd};
+
+	use super::*;
+
+	fn dev_null() -> OwnedFd {
+		std::fs::File::open("/dev/null").unwrap().into()
+	}
+
+	fn cmsgs(msg: &Msg) -> Vec<(i32, i32, Vec<u8>)> {
+		msg.cmsgs()
+			.map(|(level, ty, data)| (level, ty, data.to_vec()))
+			.collect()
+	}
+
+	/// Control data as if the kernel installed `fds` for us.
+	fn received_fds(max: usize, fds: &[RawFd]) -> Box<Msg> {
+		let mut msg = Msg::recv(std::ptr::null_mut(), 0);
+		let data: Vec<u8> = fds.iter().flat_map(|fd| fd.to_ne_bytes()).collect();
+		msg.push_cmsg(libc::SOL_SOCKET, libc::SCM_RIGHTS, &data);
+		msg.recv_fds = Some(max);
+		msg
+	}
+
+	#[test]
+	fn cmsg_space_is_aligned() {
+		let align = size_of::<usize>();
+		assert_eq!(cmsg_space(0), size_of::<libc::cmsghdr>());
+		assert_eq!(cmsg_space(1), cmsg_space(align));
+		assert_eq!(cmsg_space(align + 1), cmsg_space(0) + 2 * align);
+	}
+
+	#[test]
+	fn cmsgs_round_trip() {
+		let mut msg = Msg::send(std::ptr::null(), 0, None);
+		msg.push_cmsg(libc::SOL_UDP, libc::UDP_SEGMENT, &1200u16.to_ne_bytes());
+		msg.push_cmsg(libc::SOL_SOCKET, 1234, &[7; 9]);
+
+		assert_eq!(msg.hdr.msg_controllen, cmsg_space(2) + cmsg_space(9));
+		assert_eq!(
+			cmsgs(&msg),
+			[
+				(
+					libc::SOL_UDP,
+					libc::UDP_SEGMENT,
+					1200u16.to_ne_bytes().to_vec()
+				),
+				(libc::SOL_SOCKET, 1234, vec![7; 9]),
+			]
+		);
+	}
+
+	#[test]
+	fn unwritten_control_is_empty() {
+		let mut msg = Msg::recv(std::ptr::null_mut(), 0);
+		msg.reserve_control(cmsg_space(16));
+		msg.reserve_fds(2);
+
+		assert!(cmsgs(&msg).is_empty());
+		assert!(msg.cred().is_none());
+		assert!(msg.take_fds().is_empty());
+	}
+
+	#[test]
+	fn push_fds() {
+		let fd = dev_null();
+		let raw = fd.as_raw_fd();
+		let mut msg = Msg::send(std::ptr::null(), 0, None);
+		msg.push_fds(vec![fd]);
+
+		assert_eq!(
+			cmsgs(&msg),
+			[(
+				libc::SOL_SOCKET,
+				libc::SCM_RIGHTS,
+				raw.to_ne_bytes().to_vec()
+			)]
+		);
+		// sent fds are only kept open, never taken like received ones
+		assert_eq!(msg.fds.len(), 1);
+		assert!(msg.take_fds().is_empty());
+	}
+
+	#[test]
+	fn take_fds_up_to_max() {
+		let fds: Vec<RawFd> = (0..3).map(|_| dev_null().into_raw_fd()).collect();
+		let mut msg = received_fds(2, &fds);
+
+		let taken: Vec<_> = msg.take_fds().iter().map(AsRawFd::as_raw_fd).collect();
+		assert_eq!(taken, fds[..2]);
+		assert!(msg.take_fds().is_empty());
+	}
+
+	#[test]
+	fn take_fds_skips_truncated() {
+		let fd = dev_null().into_raw_fd();
+		let mut msg = Msg::recv(std::ptr::null_mut(), 0);
+		let mut data = fd.to_ne_bytes().to_vec();
+		data.extend([0xff; 2]);
+		msg.push_cmsg(libc::SOL_SOCKET, libc::SCM_RIGHTS, &data);
+		msg.recv_fds = Some(4);
+
+		let taken: Vec<_> = msg.take_fds().iter().map(AsRawFd::as_raw_fd).collect();
+		assert_eq!(taken, [fd]);
+	}
+
+	#[test]
+	fn cred_round_trip() {
+		let mut msg = Msg::send(std::ptr::null(), 0, None);
+		msg.push_cmsg(libc::SOL_SOCKET, libc::SCM_RIGHTS, &[]);
+		msg.push_cred(libc::ucred {
+			pid: 1,
+			uid: 2,
+			gid: 3,
+		});
+
+		let cred = msg.cred().unwrap();
+		assert_eq!((cred.pid, cred.uid, cred.gid), (1, 2, 3));
+	}
+
+	#[test]
+	fn truncated_cred() {
+		let mut msg = Msg::recv(std::ptr::null_mut(), 0);
+		msg.push_cmsg(libc::SOL_SOCKET, libc::SCM_CREDENTIALS, &[0; 8]);
+
+		assert!(msg.cred().is_none());
+	}
+}
@@ -0,0 +1,76 @@
+use std::{io, os::fd::RawFd};
+
+use io_uring::{opcode, squeue, types::Fixed};
+
+/// `SOCKET_URING_OP_GETSOCKOPT`, which `io_uring` has no builder for.
+const GETSOCKOPT_CMD_OP: u32 = 2;
+/// Offset of `cmd_op` in `struct io_uring_sqe` (`include/uapi/linux/io_uring.h`), after
+/// `opcode`, `flags`, `ioprio` and `fd`. The kernel ABI fixes it, not `io_uring`'s bindings.
+const CMD_OP_OFFSET: usize = 8;
+
+// `squeue::Entry` is a repr(C) wrapper around the 64 byte `io_uring_sqe`
+const _: () = assert!(size_of::<squeue::Entry>() == 64 && align_of::<squeue::Entry>() == 8);
+
+/// `len` as the 32-bit length the kernel takes for socket options and control messages.
+pub(crate) fn socklen(len: usize) -> libc::socklen_t {
+	libc::socklen_t::try_from(len).expect("socket option length overflows socklen_t")
+}
+
+pub(crate) fn setsockopt<T: Copy>(fd: RawFd, level: i32, name: i32, val: T) -> io::Result<()> {
+	// SAFETY: val is a valid T for the duration of the call
+	let ret = unsafe {
+		libc::setsockopt(
+			fd,
+			level,
+			name,
+			(&raw const val).cast(),
+			socklen(size_of::<T>()),
+		)
+	};
+
+	if ret == 0 {
+		Ok(())
+	} else {
+		Err(io::Error::last_os_error())
+	}
+}
+
+pub(crate) fn getsockopt<T: Copy>(fd: RawFd, level: i32, name: i32) -> io::Result<T> {
+	let mut val = std::mem::MaybeUninit::<T>::zeroed();
+	let mut len = socklen(size_of::<T>());
+
+	// SAFETY: val has room for a T and len is its size
+	let ret = unsafe { libc::getsockopt(fd, level, name, val.as_mut_ptr().cast(), &raw mut len) };
+
+	if ret == 0 {
+		// SAFETY: val was zeroed, and the kernel wrote at most len bytes into it
+		Ok(unsafe { val.assume_init() })
+	} else {
+		Err(io::Error::last_os_error())
+	}
+}
+
+/// Build a `URING_CMD` entry that sets, or with `get` gets, a socket option through the ring.
+pub(crate) fn uring_entry(
+	file: Fixed,
+	get: bool,
+	level: i32,
+	name: i32,
+	val: *mut libc::c_void,
+	len: u32,
+) -> squeue::Entry {
+	#[expect(clippy::cast_sign_loss)]
+	let entry =
+		opcode::SetSockOpt::new(file, level as u32, name as u32, val.cast_const(), len).build();
+	if !get {
+		return entry;
+	}
+
+	// the get command has the same fields as the set command, only `cmd_op` differs. `io_uring`
+	// has no builder for it, and `UringCmd16` can't set `level`, `optname` and `optlen`
+	// SAFETY: an entry is a repr(C) wrapper around a 64 byte sqe, asserted above
+	let mut raw = unsafe { std::mem::transmute::<squeue::Entry, [u8; 64]>(entry) };
+	raw[CMD_OP_OFFSET..CMD_OP_OFFSET + 4].copy_from_slice(&GETSOCKOPT_CMD_OP.to_ne_bytes());
+	// SAFETY: we only changed the command of a valid entry
+	unsafe { std::mem::transmute::<[u8; 64], squeue::Entry>(raw) }
+}
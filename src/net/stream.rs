@@ -0,0 +1,1173 @@
+use std::{
+	pin::Pin,
+	sync::{
+		Arc,
+		atomic::{AtomicUsize, Ordering},
+	},
+	task::{Context, Poll},
+};
+
+use futures::ready;
+
+use crate::{
+	Result,
+	rt::{
+		inner::WorkerMessage,
+		operation::{OperationCancelData, ProtectedOps},
+		resource::Resource,
+	},
+};
+
+use super::addr::SockAddr;
+
+pub(crate) const READ_OP_ID: u32 = 0;
+pub(crate) const WRITE_OP_ID: u32 = 1;
+pub(crate) const SHUTDOWN_OP_ID: u32 = 2;
+pub(crate) const CONNECT_OP_ID: u32 = 3;
+
+macro_rules! poll_read {
+    ($self:ident, $cx:ident, $ptr:ident, $len:ident, $buf_index:ident) => {
+		use ::futures::ready;
+		use ::io_uring::opcode;
+		use $crate::{net::stream::READ_OP_ID, rt::operation::poll_op_impl};
+
+		let this = &mut *$self;
+		return poll_op_impl!(timeout this.read_timeout, READ_OP_ID, this, $cx, false, {
+			Some(Ok(val)) => |val| Poll::Ready(Ok(val as usize)),
+			None => || {
+				let len = $len.try_into().map_err(|_| Error::BufferTooLarge)?;
+				Ok(match $buf_index {
+					Some(index) => opcode::ReadFixed::new(this.file, $ptr, len, index).build(),
+					None => opcode::Recv::new(this.file, $ptr, len).build(),
+				})
+			}
+		});
+    };
+}
+pub(crate) use poll_read;
+
+macro_rules! poll_recv_flags {
+    ($self:ident, $cx:ident, $ptr:ident, $len:ident, $flags:ident) => {
+		use ::futures::ready;
+		use ::io_uring::opcode;
+		use $crate::{net::stream::READ_OP_ID, rt::operation::poll_op_impl};
+
+		let this = &mut *$self;
+		return poll_op_impl!(timeout this.read_timeout, READ_OP_ID, this, $cx, false, {
+			Some(Ok(val)) => |val| Poll::Ready(Ok(val as usize)),
+			None => || {
+				let len = $len.try_into().map_err(|_| Error::BufferTooLarge)?;
+				Ok(opcode::Recv::new(this.file, $ptr, len).flags($flags).build())
+			}
+		});
+    };
+}
+pub(crate) use poll_recv_flags;
+
+macro_rules! poll_splice {
+    ($self:ident, $cx:ident, $id:ident, $timeout:ident, $pipe:ident, $len:ident, $into_pipe:expr) => {
+		use ::futures::ready;
+		use ::io_uring::{opcode, types::Fd};
+		use $crate::{net::stream::$id, rt::operation::poll_op_impl};
+
+		let this = &mut *$self;
+		return poll_op_impl!(timeout this.$timeout, $id, this, $cx, false, {
+			Some(Ok(val)) => |val| Poll::Ready(Ok(val as usize)),
+			None => || Ok(if $into_pipe {
+				opcode::Splice::new(this.file, -1, Fd($pipe), -1, $len).build()
+			} else {
+				opcode::Splice::new(Fd($pipe), -1, this.file, -1, $len).build()
+			})
+		});
+    };
+}
+pub(crate) use poll_splice;
+
+macro_rules! poll_ready {
+    ($self:ident, $cx:ident, $id:ident, $timeout:ident, $events:expr) => {
+		use ::futures::ready;
+		use ::io_uring::opcode;
+		use $crate::{net::stream::$id, rt::operation::poll_op_impl};
+
+		let this = &mut *$self;
+		return poll_op_impl!(timeout this.$timeout, $id, this, $cx, false, {
+			Some(Ok(val)) => |_| Poll::Ready(Ok(())),
+			None => || Ok(opcode::PollAdd::new(this.file, $events as u32).build())
+		});
+    };
+}
+pub(crate) use poll_ready;
+
+macro_rules! poll_sockopt {
+    ($self:ident, $cx:ident, $get:ident, $level:ident, $name:ident, $val:ident, $len:ident) => {
+		use ::futures::ready;
+		use $crate::{
+			net::{sockopt::uring_entry, stream::WRITE_OP_ID},
+			rt::operation::poll_op_impl,
+		};
+
+		let this = &mut *$self;
+		return poll_op_impl!(WRITE_OP_ID, this, $cx, false, {
+			Some(Ok(val)) => |val| Poll::Ready(Ok(val as usize)),
+			None => || Ok(uring_entry(this.file, $get, $level, $name, $val, $len))
+		});
+    };
+}
+pub(crate) use poll_sockopt;
+
+macro_rules! poll_read_provided {
+    ($self:ident, $cx:ident) => {
+		use ::futures::ready;
+		use ::io_uring::{opcode, squeue::Flags};
+		use $crate::{buf::BUF_RING_GROUP, net::stream::READ_OP_ID, rt::operation::poll_op_impl};
+
+		let this = &mut *$self;
+		return poll_op_impl!(timeout this.read_timeout, READ_OP_ID, this, $cx, false, {
+			Some(Ok(val)) => |val| Poll::Ready(Ok((val, this.resource.ops.flags(READ_OP_ID)))),
+			None => || {
+				this.rt.load().ok_or(Error::NoRuntime)?.buf_ring()?;
+				Ok(opcode::Recv::new(this.file, std::ptr::null_mut(), 0)
+					.buf_group(BUF_RING_GROUP)
+					.build()
+					.flags(Flags::BUFFER_SELECT))
+			}
+		});
+    };
+}
+pub(crate) use poll_read_provided;
+
+macro_rules! poll_recv_multi {
+    ($self:ident, $cx:ident) => {
+		use ::futures::ready;
+		use ::io_uring::opcode;
+		use $crate::{buf::BUF_RING_GROUP, net::stream::READ_OP_ID, rt::operation::poll_op_impl};
+
+		let this = &mut *$self;
+		return poll_op_impl!(multishot READ_OP_ID, this, $cx, false, {
+			Some(Ok(val)) => |val| Poll::Ready(Ok(val)),
+			None => || {
+				this.rt.load().ok_or(Error::NoRuntime)?.buf_ring()?;
+				Ok(opcode::RecvMulti::new(this.file, BUF_RING_GROUP).build())
+			}
+		});
+    };
+}
+pub(crate) use poll_recv_multi;
+
+macro_rules! poll_write {
+    ($self:ident, $cx:ident, $ptr:ident, $len:ident, $buf_index:ident) => {
+		use ::futures::ready;
+		use ::io_uring::opcode;
+		use $crate::{net::stream::WRITE_OP_ID, rt::operation::poll_op_impl};
+
+		let this = &mut *$self;
+		return poll_op_impl!(timeout this.write_timeout, WRITE_OP_ID, this, $cx, false, {
+			Some(Ok(val)) => |val| Poll::Ready(Ok(val as usize)),
+			None => || {
+				let len = $len.try_into().map_err(|_| Error::BufferTooLarge)?;
+				Ok(match $buf_index {
+					Some(index) => opcode::WriteFixed::new(this.file, $ptr, len, index).build(),
+					None => opcode::Send::new(this.file, $ptr, len).build(),
+				})
+			}
+		});
+    };
+}
+pub(crate) use poll_write;
+
+macro_rules! poll_send_zc {
+    ($self:ident, $cx:ident, $ptr:ident, $len:ident, $buf_index:ident) => {
+		use ::futures::ready;
+		use ::io_uring::opcode;
+		use $crate::{net::stream::WRITE_OP_ID, rt::operation::poll_op_impl};
+
+		let this = &mut *$self;
+		return poll_op_impl!(zerocopy WRITE_OP_ID, this, $cx, false, {
+			Some(Ok(val)) => |val| Poll::Ready(Ok(val as usize)),
+			None => || {
+				let len = $len.try_into().map_err(|_| Error::BufferTooLarge)?;
+				// the kernel retries short sends itself, like a blocking send would
+				Ok(opcode::SendZc::new(this.file, $ptr, len)
+					.buf_index($buf_index)
+					.flags(libc::MSG_WAITALL)
+					.build())
+			}
+		});
+    };
+}
+pub(crate) use poll_send_zc;
+
+macro_rules! poll_readv {
+    ($self:ident, $cx:ident, $iov:ident, $cnt:ident) => {
+		use ::futures::ready;
+		use ::io_uring::opcode;
+		use $crate::{net::stream::READ_OP_ID, rt::operation::poll_op_impl};
+
+		let this = &mut *$self;
+		return poll_op_impl!(timeout this.read_timeout, READ_OP_ID, this, $cx, false, {
+			Some(Ok(val)) => |val| Poll::Ready(Ok(val as usize)),
+			None => || Ok(opcode::Readv::new(this.file, $iov, $cnt).build())
+		});
+    };
+}
+pub(crate) use poll_readv;
+
+macro_rules! poll_writev {
+    ($self:ident, $cx:ident, $iov:ident, $cnt:ident) => {
+		use ::futures::ready;
+		use ::io_uring::opcode;
+		use $crate::{net::stream::WRITE_OP_ID, rt::operation::poll_op_impl};
+
+		let this = &mut *$self;
+		return poll_op_impl!(timeout this.write_timeout, WRITE_OP_ID, this, $cx, false, {
+			Some(Ok(val)) => |val| Poll::Ready(Ok(val as usize)),
+			None => || Ok(opcode::Writev::new(this.file, $iov, $cnt).build())
+		});
+    };
+}
+pub(crate) use poll_writev;
+
+macro_rules! poll_shutdown {
+    ($self:ident, $cx: ident, $how:expr) => {
+		use ::futures::ready;
+		use ::io_uring::opcode;
+		use $crate::{net::stream::SHUTDOWN_OP_ID, rt::operation::poll_op_impl};
+
+		let this = &mut *$self;
+		return poll_op_impl!(SHUTDOWN_OP_ID, this, $cx, true, {
+			Some(Ok(val)) => |_| Poll::Ready(Ok(())),
+			None => || Ok(opcode::Shutdown::new(this.file, $how).build())
+		})
+		.map_err(Into::into)
+    };
+}
+pub(crate) use poll_shutdown;
+
+macro_rules! owned_read_methods {
+	() => {
+		/// Read into an owned buffer, returning it once the read completes.
+		///
+		/// The buffer is filled from the start, up to its total capacity. Data already buffered by
+		/// `AsyncRead` is returned first.
+		pub async fn read<B: $crate::buf::IoBufMut>(
+			&mut self,
+			buf: B,
+		) -> (::std::io::Result<usize>, B) {
+			$crate::rt::owned::OwnedRead::new(self, buf).await
+		}
+
+		/// Receive into an owned buffer with `recv` flags, returning it once the recv completes.
+		///
+		/// Data already buffered by `AsyncRead` is returned first, except for `OOB`.
+		pub async fn recv_with_flags<B: $crate::buf::IoBufMut>(
+			&mut self,
+			buf: B,
+			flags: $crate::net::RecvFlags,
+		) -> (::std::io::Result<usize>, B) {
+			$crate::rt::owned::OwnedRecv::new(self, buf, flags.bits(), false).await
+		}
+
+		/// Receive into an owned buffer without removing the data from the socket, so the next
+		/// read returns it again. Useful to sniff a protocol before handing the stream on.
+		pub async fn peek<B: $crate::buf::IoBufMut>(
+			&mut self,
+			buf: B,
+		) -> (::std::io::Result<usize>, B) {
+			self.recv_with_flags(buf, $crate::net::RecvFlags::PEEK)
+				.await
+		}
+
+		/// Fill the whole owned buffer, up to its total capacity, using `MSG_WAITALL` so the
+		/// kernel waits for all of it in a single recv.
+		///
+		/// Fails with `UnexpectedEof` if the peer shuts down first, in which case the buffer
+		/// holds what was received.
+		pub async fn read_exact_waitall<B: $crate::buf::IoBufMut>(
+			&mut self,
+			buf: B,
+		) -> (::std::io::Result<()>, B) {
+			let (ret, buf) =
+				$crate::rt::owned::OwnedRecv::new(self, buf, ::libc::MSG_WAITALL, true).await;
+			(ret.map(|_| ()), buf)
+		}
+
+		/// Read into several owned buffers with a single `Readv`, filling each up to its total
+		/// capacity before moving on to the next.
+		pub async fn read_vectored<B: $crate::buf::IoBufMut>(
+			&mut self,
+			bufs: Vec<B>,
+		) -> (::std::io::Result<usize>, Vec<B>) {
+			$crate::rt::owned::OwnedReadv::new(self, bufs).await
+		}
+
+		/// Read into a buffer the kernel picks from the runtime's provided buffer ring once data
+		/// arrives, so no buffer is held while waiting. Returns `None` at EOF.
+		///
+		/// Needs [`UringRuntimeBuilder::provided_buffers`](crate::rt::UringRuntimeBuilder::provided_buffers).
+		/// Fails with `ENOBUFS` if every buffer is leased out, and with
+		/// [`Error::ReadBuffered`](crate::Error::ReadBuffered) if `AsyncRead` still buffers data.
+		pub async fn recv_provided(
+			&mut self,
+		) -> ::std::io::Result<Option<$crate::buf::ProvidedBuf>> {
+			$crate::rt::owned::OwnedProvidedRead::new(self).await
+		}
+
+		/// Receive into buffers from the runtime's provided buffer ring with a single multishot
+		/// recv, yielding each one as data arrives until EOF.
+		///
+		/// Needs [`UringRuntimeBuilder::provided_buffers`](crate::rt::UringRuntimeBuilder::provided_buffers).
+		/// Yields `ENOBUFS` if every buffer is leased out; the recv is re-armed on the next poll.
+		/// Yields [`Error::ReadBuffered`](crate::Error::ReadBuffered) if `AsyncRead` still buffers
+		/// data.
+		pub fn recv_stream(
+			&mut self,
+		) -> impl ::futures::Stream<Item = ::std::io::Result<$crate::buf::ProvidedBuf>> + '_ {
+			$crate::rt::owned::RecvStream::new(self)
+		}
+	};
+}
+pub(crate) use owned_read_methods;
+
+macro_rules! owned_write_methods {
+	() => {
+		/// Write the initialized part of an owned buffer, returning it once the write completes.
+		pub async fn write<B: $crate::buf::IoBuf>(
+			&mut self,
+			buf: B,
+		) -> (::std::io::Result<usize>, B) {
+			$crate::rt::owned::OwnedWrite::new(self, buf, false).await
+		}
+
+		/// Write the initialized parts of several owned buffers, in order, with a single
+		/// `Writev`. Like [`write`](Self::write), this may write less than all of them.
+		pub async fn write_vectored<B: $crate::buf::IoBuf>(
+			&mut self,
+			bufs: Vec<B>,
+		) -> (::std::io::Result<usize>, Vec<B>) {
+			$crate::rt::owned::OwnedWritev::new(self, bufs).await
+		}
+
+		/// Write the entire initialized part of an owned buffer, returning it once done.
+		pub async fn write_all<B: $crate::buf::IoBuf>(
+			&mut self,
+			buf: B,
+		) -> (::std::io::Result<()>, B) {
+			let (ret, buf) = $crate::rt::owned::OwnedWrite::new(self, buf, true).await;
+			(ret.map(|_| ()), buf)
+		}
+	};
+}
+pub(crate) use owned_write_methods;
+
+macro_rules! read_timeout_methods {
+	() => {
+		/// Fail reads that don't complete within `timeout` with `TimedOut`, or wait forever with
+		/// `None`, which is the default.
+		///
+		/// The timeout is linked to every recv with `LinkTimeout`, so the kernel cancels the recv
+		/// itself, unlike dropping the read future. Zero-copy and multishot ops aren't affected.
+		pub fn set_read_timeout(&mut self, timeout: Option<::std::time::Duration>) {
+			self.read_timeout = timeout;
+		}
+
+		pub fn read_timeout(&self) -> Option<::std::time::Duration> {
+			self.read_timeout
+		}
+	};
+}
+pub(crate) use read_timeout_methods;
+
+macro_rules! write_timeout_methods {
+	() => {
+		/// Fail writes that don't complete within `timeout` with `TimedOut`, or wait forever with
+		/// `None`, which is the default.
+		///
+		/// The timeout is linked to every send with `LinkTimeout`, so the kernel cancels the send
+		/// itself. Data a timed out send already passed to the socket isn't taken back.
+		pub fn set_write_timeout(&mut self, timeout: Option<::std::time::Duration>) {
+			self.write_timeout = timeout;
+		}
+
+		pub fn write_timeout(&self) -> Option<::std::time::Duration> {
+			self.write_timeout
+		}
+	};
+}
+pub(crate) use write_timeout_methods;
+
+macro_rules! send_zc_methods {
+	() => {
+		/// Send the entire initialized part of an owned buffer without copying it into the
+		/// kernel (`SendZc`), returning it once the kernel no longer references it.
+		///
+		/// Buffered writes go out first. This waits for the send's notification rather than just
+		/// its result, so it only pays off for large buffers. Registered buffers from
+		/// [`UringRuntime::fixed_buf`](crate::rt::UringRuntime::fixed_buf) are sent without
+		/// pinning their pages again.
+		pub async fn send_zc<B: $crate::buf::IoBuf>(
+			&mut self,
+			buf: B,
+		) -> (::std::io::Result<usize>, B) {
+			$crate::rt::owned::OwnedSendZc::new(self, buf).await
+		}
+	};
+}
+pub(crate) use send_zc_methods;
+
+/// Define a stream socket type, registered with the runtime, along with its owned halves.
+///
+/// Everything but creating the socket is the same for every kind of stream socket.
+macro_rules! stream_types {
+	(
+		$stream:ident,
+		$std:ty,
+		$read:ident,
+		$write:ident,
+		$borrowed_read:ident,
+		$borrowed_write:ident,
+		$reunite:ident
+	) => {
+		pub struct $read {
+			rt: $crate::rt::UringDataHandle,
+			resource: $crate::rt::resource::Resource,
+			sender: $crate::rt::inner::RuntimeWorkerChannel,
+
+			file: ::io_uring::types::Fixed,
+			// owned by the runtime, which keeps it open until the resource is removed
+			fd: ::std::os::fd::RawFd,
+
+			read_buf: $crate::compat::ReadBuffer,
+			read_timeout: Option<::std::time::Duration>,
+
+			owner: $crate::net::stream::SplitOwner,
+			shutdown_on_drop: bool,
+			destructuring: bool,
+		}
+		pub struct $write {
+			rt: $crate::rt::UringDataHandle,
+			resource: $crate::rt::resource::Resource,
+			sender: $crate::rt::inner::RuntimeWorkerChannel,
+
+			file: ::io_uring::types::Fixed,
+			// owned by the runtime, which keeps it open until the resource is removed
+			fd: ::std::os::fd::RawFd,
+
+			write_buf: $crate::compat::WriteBuffer,
+			write_timeout: Option<::std::time::Duration>,
+
+			owner: $crate::net::stream::SplitOwner,
+			shutdown_on_drop: bool,
+			destructuring: bool,
+		}
+		/// Read half of a stream borrowed with `split`.
+		pub struct $borrowed_read<'a> {
+			rt: $crate::rt::UringDataHandle,
+			// polls the stream's ops in its place
+			resource: $crate::rt::resource::BorrowedResource<'a, 1>,
+
+			file: ::io_uring::types::Fixed,
+			fd: ::std::os::fd::RawFd,
+
+			read_buf: &'a mut $crate::compat::ReadBuffer,
+			read_timeout: Option<::std::time::Duration>,
+		}
+		/// Write half of a stream borrowed with `split`.
+		pub struct $borrowed_write<'a> {
+			rt: $crate::rt::UringDataHandle,
+			resource: $crate::rt::resource::BorrowedResource<'a, 2>,
+
+			file: ::io_uring::types::Fixed,
+			fd: ::std::os::fd::RawFd,
+
+			write_buf: &'a mut $crate::compat::WriteBuffer,
+			write_timeout: Option<::std::time::Duration>,
+		}
+		pub struct $stream {
+			rt: $crate::rt::UringDataHandle,
+			resource: $crate::rt::resource::Resource,
+			sender: $crate::rt::inner::RuntimeWorkerChannel,
+
+			file: ::io_uring::types::Fixed,
+			// owned by the runtime, which keeps it open until the resource is removed
+			fd: ::std::os::fd::RawFd,
+
+			read_buf: $crate::compat::ReadBuffer,
+			write_buf: $crate::compat::WriteBuffer,
+			read_timeout: Option<::std::time::Duration>,
+			write_timeout: Option<::std::time::Duration>,
+
+			destructuring: bool,
+		}
+
+		$crate::net::stream::stream_types!(@ops $read);
+		$crate::net::stream::stream_types!(@ops $write);
+		$crate::net::stream::stream_types!(@ops $borrowed_read<'a>);
+		$crate::net::stream::stream_types!(@ops $borrowed_write<'a>);
+		$crate::net::stream::stream_types!(@ops $stream);
+
+		$crate::net::stream::stream_types!(@read $read);
+		$crate::net::stream::stream_types!(@read $borrowed_read<'a>);
+		$crate::net::stream::stream_types!(@read $stream);
+		$crate::net::stream::stream_types!(@write $write);
+		$crate::net::stream::stream_types!(@write $borrowed_write<'a>);
+		$crate::net::stream::stream_types!(@write $stream);
+		$crate::net::stream::stream_types!(@unbuffer read $read);
+		$crate::net::stream::stream_types!(@unbuffer read $borrowed_read<'a>);
+		$crate::net::stream::stream_types!(@unbuffer write $write);
+		$crate::net::stream::stream_types!(@unbuffer write $borrowed_write<'a>);
+		$crate::net::stream::stream_types!(@unbuffer $stream);
+
+		impl $crate::rt::operation::Splice for $stream {
+			unsafe fn poll_splice_in(
+				&mut self,
+				cx: &mut ::std::task::Context<'_>,
+				pipe: ::std::os::fd::RawFd,
+				len: u32,
+			) -> ::std::task::Poll<$crate::Result<usize>> {
+				$crate::net::stream::poll_splice!(self, cx, READ_OP_ID, read_timeout, pipe, len, true);
+			}
+
+			unsafe fn poll_splice_out(
+				&mut self,
+				cx: &mut ::std::task::Context<'_>,
+				pipe: ::std::os::fd::RawFd,
+				len: u32,
+			) -> ::std::task::Poll<$crate::Result<usize>> {
+				$crate::net::stream::poll_splice!(self, cx, WRITE_OP_ID, write_timeout, pipe, len, false);
+			}
+
+			fn poll_readable(
+				&mut self,
+				cx: &mut ::std::task::Context<'_>,
+			) -> ::std::task::Poll<$crate::Result<()>> {
+				$crate::net::stream::poll_ready!(self, cx, READ_OP_ID, read_timeout, ::libc::POLLIN);
+			}
+
+			fn poll_writable(
+				&mut self,
+				cx: &mut ::std::task::Context<'_>,
+			) -> ::std::task::Poll<$crate::Result<()>> {
+				$crate::net::stream::poll_ready!(self, cx, WRITE_OP_ID, write_timeout, ::libc::POLLOUT);
+			}
+
+			fn poll_shutdown_write(
+				&mut self,
+				cx: &mut ::std::task::Context<'_>,
+			) -> ::std::task::Poll<$crate::Result<()>> {
+				$crate::net::stream::poll_shutdown!(self, cx, ::libc::SHUT_WR);
+			}
+		}
+
+		impl $crate::net::stream::RawConnect for $stream {
+			unsafe fn poll_connect_raw(
+				&mut self,
+				cx: &mut ::std::task::Context<'_>,
+				addr: *const ::libc::sockaddr,
+				len: ::libc::socklen_t,
+			) -> ::std::task::Poll<$crate::Result<()>> {
+				use ::futures::ready;
+				use ::io_uring::opcode;
+				use $crate::{net::stream::CONNECT_OP_ID, rt::operation::poll_op_impl};
+
+				let this = self;
+				poll_op_impl!(CONNECT_OP_ID, this, cx, false, {
+					Some(Ok(val)) => |_| Poll::Ready(Ok(())),
+					None => || Ok(opcode::Connect::new(this.file, addr, len).build())
+				})
+			}
+		}
+
+		impl $read {
+			$crate::net::stream::owned_read_methods!();
+			$crate::net::stream::read_timeout_methods!();
+
+			/// Shut down the read direction when this half is dropped while the write half is
+			/// still alive, so local reads return EOF. The peer isn't told, and its sends still
+			/// succeed.
+			pub fn set_shutdown_on_drop(&mut self, shutdown: bool) {
+				self.shutdown_on_drop = shutdown;
+			}
+
+			/// Put the stream back together from the halves `into_split` returned, failing if
+			/// `other` comes from a different stream.
+			#[expect(clippy::result_large_err)]
+			pub fn reunite(mut self, mut other: $write) -> ::std::result::Result<$stream, $reunite> {
+				if !self.owner.same(&other.owner) {
+					return Err($reunite(self, other));
+				}
+				self.destructuring = true;
+				other.destructuring = true;
+
+				// each half tracked the ops it polled itself
+				let mut resource = self.resource.dup();
+				resource.ops.merge(&other.resource.ops);
+
+				Ok($stream {
+					rt: self.rt.clone(),
+					resource,
+					sender: self.sender.clone(),
+					file: self.file,
+					fd: self.fd,
+
+					read_buf: ::std::mem::take(&mut self.read_buf),
+					write_buf: ::std::mem::take(&mut other.write_buf),
+					read_timeout: self.read_timeout,
+					write_timeout: other.write_timeout,
+
+					destructuring: false,
+				})
+			}
+		}
+
+		impl $write {
+			$crate::net::stream::owned_write_methods!();
+			$crate::net::stream::write_timeout_methods!();
+
+			/// Shut down the write direction when this half is dropped while the read half is
+			/// still alive, so the peer sees EOF. The shutdown waits for buffered writes that
+			/// were already submitted.
+			pub fn set_shutdown_on_drop(&mut self, shutdown: bool) {
+				self.shutdown_on_drop = shutdown;
+			}
+
+			/// Put the stream back together, see [`reunite`]($read::reunite).
+			#[expect(clippy::result_large_err)]
+			pub fn reunite(self, other: $read) -> ::std::result::Result<$stream, $reunite> {
+				other.reunite(self)
+			}
+		}
+
+		impl $borrowed_read<'_> {
+			$crate::net::stream::owned_read_methods!();
+		}
+
+		impl $borrowed_write<'_> {
+			$crate::net::stream::owned_write_methods!();
+		}
+
+		/// Error returned by `reunite` for halves of different streams, which are handed back.
+		pub struct $reunite(pub $read, pub $write);
+
+		impl ::std::fmt::Debug for $reunite {
+			fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+				f.debug_tuple(stringify!($reunite)).finish_non_exhaustive()
+			}
+		}
+
+		impl ::std::fmt::Display for $reunite {
+			fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+				f.write_str("tried to reunite halves of different streams")
+			}
+		}
+
+		impl ::std::error::Error for $reunite {}
+
+		impl $stream {
+			$crate::net::stream::owned_read_methods!();
+			$crate::net::stream::owned_write_methods!();
+			$crate::net::stream::read_timeout_methods!();
+			$crate::net::stream::write_timeout_methods!();
+
+			pub(crate) async fn new(
+				std: $std,
+				rt: $crate::rt::UringDataHandle,
+				sender: $crate::rt::inner::RuntimeWorkerChannel,
+			) -> $crate::Result<Self> {
+				std.set_nonblocking(true)?;
+				let fd = ::std::os::fd::OwnedFd::from(std);
+				let raw = ::std::os::fd::AsRawFd::as_raw_fd(&fd);
+				let (resource, file) =
+					$crate::rt::resource::Resource::register_fd(&sender, fd).await?;
+
+				Ok(Self {
+					rt,
+					resource,
+					sender,
+					file,
+					fd: raw,
+
+					read_buf: $crate::compat::ReadBuffer::default(),
+					write_buf: $crate::compat::WriteBuffer::default(),
+					read_timeout: None,
+					write_timeout: None,
+
+					destructuring: false,
+				})
+			}
+
+			/// Connect a stream whose socket isn't connected yet.
+			pub(crate) fn connect_raw(
+				&mut self,
+				addr: Box<$crate::net::addr::SockAddr>,
+			) -> $crate::net::stream::Connect<'_, Self> {
+				$crate::net::stream::Connect::new(self, addr)
+			}
+
+			/// Flush buffered writes and shut down both directions of the connection.
+			///
+			/// The fd itself is closed once the runtime is done with it, same as on drop, but
+			/// errors from the final flush and shutdown are returned instead of being lost.
+			pub async fn close(mut self) -> ::std::io::Result<()> {
+				::std::future::poll_fn(|cx| {
+					$crate::compat::WriteBuffer::poll_flush(&mut self, cx)
+				})
+				.await?;
+				self.resource.set_closing();
+
+				::std::future::poll_fn(|cx| {
+					let this = &mut self;
+					$crate::net::stream::poll_shutdown!(this, cx, ::libc::SHUT_RDWR);
+				})
+				.await
+			}
+
+			/// Remove the stream from the runtime and return it as a std stream, for code that
+			/// needs a plain socket, along with data already read from it through `AsyncRead` but
+			/// not consumed yet.
+			///
+			/// Buffered writes are flushed and an in-flight read is cancelled, keeping whatever it
+			/// read before the cancel took effect. The fd is handed back once the runtime is done
+			/// with every op on it. The returned stream is still in nonblocking mode.
+			pub async fn into_std(mut self) -> $crate::Result<($std, Vec<u8>)> {
+				::std::future::poll_fn(|cx| {
+					$crate::compat::WriteBuffer::poll_flush(&mut self, cx)
+				})
+				.await?;
+
+				let mut interrupted = false;
+				::std::future::poll_fn(|cx| {
+					$crate::compat::ReadBuffer::poll_interrupt(&mut self, cx, &mut interrupted)
+				})
+				.await?;
+				let leftover = self.read_buf.take_remaining();
+
+				// the runtime owns the fd from here on, even if we are dropped before it's handed
+				// back
+				self.destructuring = true;
+				match self.resource.deregister_fd(&self.sender).await {
+					Ok(fd) => Ok((<$std>::from(fd), leftover)),
+					Err(err) => {
+						// the runtime never took the fd, so it's closed like on drop
+						self.destructuring = false;
+						Err(err)
+					}
+				}
+			}
+
+			/// Split into halves that borrow the stream, so it can be read and written at the same
+			/// time. Ops in flight when a half is dropped carry on in the stream.
+			pub fn split(&mut self) -> ($borrowed_read<'_>, $borrowed_write<'_>) {
+				let (file, fd) = (self.file, self.fd);
+				let (read, write) = self.resource.split_mut(
+					[$crate::net::stream::READ_OP_ID],
+					[
+						$crate::net::stream::WRITE_OP_ID,
+						$crate::net::stream::SHUTDOWN_OP_ID,
+					],
+				);
+
+				(
+					$borrowed_read {
+						rt: self.rt.clone(),
+						resource: read,
+						file,
+						fd,
+						read_buf: &mut self.read_buf,
+						read_timeout: self.read_timeout,
+					},
+					$borrowed_write {
+						rt: self.rt.clone(),
+						resource: write,
+						file,
+						fd,
+						write_buf: &mut self.write_buf,
+						write_timeout: self.write_timeout,
+					},
+				)
+			}
+
+			/// Split into owned halves, for example to read and write from different tasks. The
+			/// socket is closed once both halves are dropped, or they can be put back together
+			/// with `reunite`.
+			pub fn into_split(mut self) -> ($read, $write) {
+				self.destructuring = true;
+				let (read_owner, write_owner) = $crate::net::stream::SplitOwner::pair();
+				(
+					$read {
+						resource: self.resource.dup_only(&[
+							$crate::net::stream::READ_OP_ID,
+							$crate::net::stream::CONNECT_OP_ID,
+						]),
+						rt: self.rt.clone(),
+						sender: self.sender.clone(),
+						file: self.file,
+						fd: self.fd,
+						read_buf: ::std::mem::take(&mut self.read_buf),
+						read_timeout: self.read_timeout,
+						owner: read_owner,
+						shutdown_on_drop: false,
+						destructuring: false,
+					},
+					$write {
+						resource: self.resource.dup_only(&[
+							$crate::net::stream::WRITE_OP_ID,
+							$crate::net::stream::SHUTDOWN_OP_ID,
+						]),
+						rt: self.rt.clone(),
+						sender: self.sender.clone(),
+						file: self.file,
+						fd: self.fd,
+						write_buf: ::std::mem::take(&mut self.write_buf),
+						write_timeout: self.write_timeout,
+						owner: write_owner,
+						shutdown_on_drop: false,
+						destructuring: false,
+					},
+				)
+			}
+		}
+
+		impl Drop for $read {
+			fn drop(&mut self) {
+				if !self.destructuring {
+					let mut read_buf = ::std::mem::take(&mut self.read_buf);
+					read_buf.cancel(self);
+					// the other half only tracks its own ops, so closing the socket has to see
+					// ours in the ops' shared state
+					for id in [
+						$crate::net::stream::READ_OP_ID,
+						$crate::net::stream::CONNECT_OP_ID,
+					] {
+						$crate::rt::operation::ProtectedOps::reclaim_op(self, id);
+					}
+
+					let shutdown = self.shutdown_on_drop.then_some(::libc::SHUT_RD);
+					if let Some(message) = self.owner.release(&self.resource, shutdown) {
+						let _ = self.sender.send(message);
+					}
+				}
+			}
+		}
+		impl Drop for $write {
+			fn drop(&mut self) {
+				if !self.destructuring {
+					let mut write_buf = ::std::mem::take(&mut self.write_buf);
+					write_buf.detach(self);
+					for id in [
+						$crate::net::stream::WRITE_OP_ID,
+						$crate::net::stream::SHUTDOWN_OP_ID,
+					] {
+						$crate::rt::operation::ProtectedOps::release_op(self, id);
+					}
+
+					// the shutdown waits for the detached write, so it doesn't cut off its data
+					let shutdown = self.shutdown_on_drop.then_some(::libc::SHUT_WR);
+					if let Some(message) = self.owner.release(&self.resource, shutdown) {
+						let _ = self.sender.send(message);
+					}
+				}
+			}
+		}
+		impl Drop for $stream {
+			fn drop(&mut self) {
+				if !self.destructuring {
+					let mut read_buf = ::std::mem::take(&mut self.read_buf);
+					read_buf.cancel(self);
+					let mut write_buf = ::std::mem::take(&mut self.write_buf);
+					write_buf.detach(self);
+
+					let _ = self.sender.send(
+						$crate::rt::inner::WorkerMessage::CloseResource(self.resource.dup()),
+					);
+				}
+			}
+		}
+	};
+	(@ops $ty:ident $(<$lt:lifetime>)?) => {
+		impl$(<$lt>)? $crate::rt::operation::ProtectedOps for $ty$(<$lt>)? {
+			const READ_OP_ID: u32 = $crate::net::stream::READ_OP_ID;
+			const WRITE_OP_ID: u32 = $crate::net::stream::WRITE_OP_ID;
+
+			fn get_rt(&self) -> &$crate::rt::UringDataHandle {
+				&self.rt
+			}
+
+			fn cancel_op(&mut self, id: u32, data: $crate::rt::operation::OperationCancelData) {
+				self.resource.cancel(self.rt.load(), id, data);
+			}
+
+			fn detach_op(&mut self, id: u32, data: $crate::rt::operation::OperationCancelData) {
+				let _ = self.resource.detach(self.rt.load(), id, data);
+			}
+
+			fn submission(&self, id: u32) -> $crate::rt::operation::OperationPollState {
+				self.resource.ops.submission(id)
+			}
+
+			fn interrupt_op(&self, id: u32) {
+				self.resource.interrupt(self.rt.load(), id);
+			}
+		}
+
+		impl$(<$lt>)? ::std::os::fd::AsRawFd for $ty$(<$lt>)? {
+			fn as_raw_fd(&self) -> ::std::os::fd::RawFd {
+				self.fd
+			}
+		}
+		impl$(<$lt>)? ::std::os::fd::AsFd for $ty$(<$lt>)? {
+			fn as_fd(&self) -> ::std::os::fd::BorrowedFd<'_> {
+				// SAFETY: the runtime keeps the fd open for as long as we exist
+				unsafe { ::std::os::fd::BorrowedFd::borrow_raw(self.fd) }
+			}
+		}
+	};
+	(@unbuffer read $ty:ident $(<$lt:lifetime>)?) => {
+		impl$(<$lt>)? $crate::compat::Unbuffer for $ty$(<$lt>)? {
+			fn poll_unbuffer(
+				&mut self,
+				cx: &mut ::std::task::Context<'_>,
+				interrupted: &mut bool,
+			) -> ::std::task::Poll<::std::io::Result<Vec<u8>>> {
+				$crate::compat::ReadBuffer::poll_unbuffer(self, cx, interrupted)
+			}
+		}
+	};
+	(@unbuffer write $ty:ident $(<$lt:lifetime>)?) => {
+		impl$(<$lt>)? $crate::compat::Unbuffer for $ty$(<$lt>)? {
+			fn poll_unbuffer(
+				&mut self,
+				cx: &mut ::std::task::Context<'_>,
+				_interrupted: &mut bool,
+			) -> ::std::task::Poll<::std::io::Result<Vec<u8>>> {
+				::futures::ready!($crate::compat::WriteBuffer::poll_prepare(self, cx))?;
+				::std::task::Poll::Ready(Ok(Vec::new()))
+			}
+		}
+	};
+	(@unbuffer $ty:ident $(<$lt:lifetime>)?) => {
+		impl$(<$lt>)? $crate::compat::Unbuffer for $ty$(<$lt>)? {
+			fn poll_unbuffer(
+				&mut self,
+				cx: &mut ::std::task::Context<'_>,
+				interrupted: &mut bool,
+			) -> ::std::task::Poll<::std::io::Result<Vec<u8>>> {
+				// flushed before the read, so it's done by the time the read is
+				::futures::ready!($crate::compat::WriteBuffer::poll_prepare(self, cx))?;
+				$crate::compat::ReadBuffer::poll_unbuffer(self, cx, interrupted)
+			}
+		}
+	};
+	(@read $ty:ident $(<$lt:lifetime>)?) => {
+		impl$(<$lt>)? $crate::rt::operation::RawRead for $ty$(<$lt>)? {
+			unsafe fn poll_read_raw(
+				&mut self,
+				cx: &mut ::std::task::Context<'_>,
+				ptr: *mut u8,
+				len: usize,
+				buf_index: Option<u16>,
+			) -> ::std::task::Poll<$crate::Result<usize>> {
+				$crate::net::stream::poll_read!(self, cx, ptr, len, buf_index);
+			}
+		}
+		impl$(<$lt>)? $crate::rt::operation::FlaggedRead for $ty$(<$lt>)? {
+			unsafe fn poll_recv_flags(
+				&mut self,
+				cx: &mut ::std::task::Context<'_>,
+				ptr: *mut u8,
+				len: usize,
+				flags: i32,
+			) -> ::std::task::Poll<$crate::Result<usize>> {
+				$crate::net::stream::poll_recv_flags!(self, cx, ptr, len, flags);
+			}
+		}
+		impl$(<$lt>)? $crate::rt::operation::VectoredRead for $ty$(<$lt>)? {
+			unsafe fn poll_readv_raw(
+				&mut self,
+				cx: &mut ::std::task::Context<'_>,
+				iov: *const ::libc::iovec,
+				cnt: u32,
+			) -> ::std::task::Poll<$crate::Result<usize>> {
+				$crate::net::stream::poll_readv!(self, cx, iov, cnt);
+			}
+		}
+		impl$(<$lt>)? $crate::rt::operation::ProvidedRead for $ty$(<$lt>)? {
+			fn poll_read_provided(
+				&mut self,
+				cx: &mut ::std::task::Context<'_>,
+			) -> ::std::task::Poll<$crate::Result<(u32, u32)>> {
+				$crate::net::stream::poll_read_provided!(self, cx);
+			}
+
+			fn poll_recv_multi(
+				&mut self,
+				cx: &mut ::std::task::Context<'_>,
+			) -> ::std::task::Poll<$crate::Result<(u32, u32)>> {
+				$crate::net::stream::poll_recv_multi!(self, cx);
+			}
+		}
+		impl$(<$lt>)? $crate::compat::BufferedRead for $ty$(<$lt>)? {
+			fn read_buffer(&mut self) -> &mut $crate::compat::ReadBuffer {
+				::std::borrow::BorrowMut::borrow_mut(&mut self.read_buf)
+			}
+		}
+		impl$(<$lt>)? ::tokio::io::AsyncRead for $ty$(<$lt>)? {
+			fn poll_read(
+				mut self: ::std::pin::Pin<&mut Self>,
+				cx: &mut ::std::task::Context<'_>,
+				buf: &mut ::tokio::io::ReadBuf<'_>,
+			) -> ::std::task::Poll<::std::io::Result<()>> {
+				$crate::compat::ReadBuffer::poll_read(&mut *self, cx, buf)
+			}
+		}
+	};
+	(@write $ty:ident $(<$lt:lifetime>)?) => {
+		impl$(<$lt>)? $crate::rt::operation::RawWrite for $ty$(<$lt>)? {
+			unsafe fn poll_write_raw(
+				&mut self,
+				cx: &mut ::std::task::Context<'_>,
+				ptr: *const u8,
+				len: usize,
+				buf_index: Option<u16>,
+			) -> ::std::task::Poll<$crate::Result<usize>> {
+				$crate::net::stream::poll_write!(self, cx, ptr, len, buf_index);
+			}
+		}
+		impl$(<$lt>)? $crate::rt::operation::VectoredWrite for $ty$(<$lt>)? {
+			unsafe fn poll_writev_raw(
+				&mut self,
+				cx: &mut ::std::task::Context<'_>,
+				iov: *const ::libc::iovec,
+				cnt: u32,
+			) -> ::std::task::Poll<$crate::Result<usize>> {
+				$crate::net::stream::poll_writev!(self, cx, iov, cnt);
+			}
+		}
+		impl$(<$lt>)? $crate::compat::BufferedWrite for $ty$(<$lt>)? {
+			fn write_buffer(&mut self) -> &mut $crate::compat::WriteBuffer {
+				::std::borrow::BorrowMut::borrow_mut(&mut self.write_buf)
+			}
+		}
+		impl$(<$lt>)? ::tokio::io::AsyncWrite for $ty$(<$lt>)? {
+			fn poll_write(
+				mut self: ::std::pin::Pin<&mut Self>,
+				cx: &mut ::std::task::Context<'_>,
+				buf: &[u8],
+			) -> ::std::task::Poll<::std::io::Result<usize>> {
+				$crate::compat::WriteBuffer::poll_write(&mut *self, cx, buf)
+			}
+
+			fn poll_write_vectored(
+				mut self: ::std::pin::Pin<&mut Self>,
+				cx: &mut ::std::task::Context<'_>,
+				bufs: &[::std::io::IoSlice<'_>],
+			) -> ::std::task::Poll<::std::io::Result<usize>> {
+				$crate::compat::WriteBuffer::poll_write_vectored(&mut *self, cx, bufs)
+			}
+
+			fn is_write_vectored(&self) -> bool {
+				true
+			}
+
+			fn poll_flush(
+				mut self: ::std::pin::Pin<&mut Self>,
+				cx: &mut ::std::task::Context<'_>,
+			) -> ::std::task::Poll<::std::io::Result<()>> {
+				$crate::compat::WriteBuffer::poll_flush(&mut *self, cx)
+			}
+
+			fn poll_shutdown(
+				mut self: ::std::pin::Pin<&mut Self>,
+				cx: &mut ::std::task::Context<'_>,
+			) -> ::std::task::Poll<::std::io::Result<()>> {
+				::futures::ready!($crate::compat::WriteBuffer::poll_flush(&mut *self, cx))?;
+				$crate::net::stream::poll_shutdown!(self, cx, ::libc::SHUT_WR);
+			}
+		}
+	};
+}
+pub(crate) use stream_types;
+
+pub(crate) trait RawConnect: ProtectedOps {
+	/// Connect to the address at `addr`.
+	///
+	/// SAFETY: make sure the address stays alive until the op completes
+	unsafe fn poll_connect_raw(
+		&mut self,
+		cx: &mut Context<'_>,
+		addr: *const libc::sockaddr,
+		len: libc::socklen_t,
+	) -> Poll<Result<()>>;
+}
+
+/// Connect op that owns the address until the kernel is done with it.
+pub(crate) struct Connect<'a, T: RawConnect> {
+	io: &'a mut T,
+	addr: Option<Box<SockAddr>>,
+}
+
+impl<'a, T: RawConnect> Connect<'a, T> {
+	pub fn new(io: &'a mut T, addr: Box<SockAddr>) -> Self {
+		Self {
+			io,
+			addr: Some(addr),
+		}
+	}
+}
+
+impl<T: RawConnect> Future for Connect<'_, T> {
+	type Output = Result<()>;
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		let this = &mut *self;
+		let addr = this.addr.as_ref().expect("polled after completion");
+
+		// SAFETY: the address is handed to the runtime if we are dropped before the op completes
+		let ret = ready!(unsafe { this.io.poll_connect_raw(cx, addr.as_ptr(), addr.len()) });
+		this.addr = None;
+
+		Poll::Ready(ret)
+	}
+}
+
+impl<T: RawConnect> Drop for Connect<'_, T> {
+	fn drop(&mut self) {
+		if let Some(addr) = self.addr.take() {
+			self.io.cancel_op(
+				CONNECT_OP_ID,
+				OperationCancelData {
+					wake: true,
+					buf: Box::new(addr),
+					release: None,
+				},
+			);
+		}
+	}
+}
+
+/// Shared by the owned halves of a stream, so the socket is only closed once both are dropped.
+pub(crate) struct SplitOwner(Arc<AtomicUsize>);
+
+impl SplitOwner {
+	pub fn pair() -> (Self, Self) {
+		let count = Arc::new(AtomicUsize::new(2));
+		(Self(count.clone()), Self(count))
+	}
+
+	/// Whether both halves come from the same `into_split`.
+	pub fn same(&self, other: &Self) -> bool {
+		Arc::ptr_eq(&self.0, &other.0)
+	}
+
+	/// Give up a half, returning the message that closes the socket if it was the last one, or
+	/// shuts down `shutdown` if it's set.
+	pub fn release(&self, resource: &Resource, shutdown: Option<i32>) -> Option<WorkerMessage> {
+		if self.0.fetch_sub(1, Ordering::AcqRel) == 1 {
+			Some(WorkerMessage::CloseResource(resource.dup()))
+		} else {
+			shutdown.map(|how| WorkerMessage::ShutdownResource {
+				resource: resource.dup(),
+				how,
+			})
+		}
+	}
+}
@@ -0,0 +1,422 @@
+use std::{
+	io,
+	mem::ManuallyDrop,
+	net::SocketAddr,
+	os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+	pin::Pin,
+	task::{Context, Poll},
+};
+
+use futures::{Stream, future::BoxFuture, ready};
+use io_uring::{
+	opcode,
+	squeue::{self, Flags},
+	types::{DestinationSlot, Fixed},
+};
+
+use crate::{
+	Error, Result,
+	net::{addr::SockAddr, close_fd, sockopt},
+	rt::{
+		UringDataHandle, UringRuntime,
+		inner::{RuntimeWorkerChannel, WorkerMessage},
+		oneshot::run_oneshot,
+		operation::{EventData, OperationCancelData, poll_op_impl},
+		resource::Resource,
+	},
+};
+
+use super::TcpStream;
+
+const ACCEPT_OP_ID: u32 = 0;
+const ACCEPT_MULTI_OP_ID: u32 = 1;
+// creating, binding and listening on a socket in the file table, linked into one chain before
+// any accept can use the ops
+const SOCKET_OP_ID: u32 = 0;
+const REUSEADDR_OP_ID: u32 = 1;
+const BIND_OP_ID: u32 = 2;
+const LISTEN_OP_ID: u32 = 3;
+
+// only read by the kernel, so it can never go away while an op uses it
+static REUSEADDR: libc::c_int = 1;
+
+/// Where the listener's socket lives.
+enum Socket {
+	// owned by the runtime, which keeps it open until the resource is removed
+	Fd(RawFd),
+	// only in the file table, so the address it was bound to is kept instead
+	Direct(SocketAddr),
+}
+
+pub struct TcpListener {
+	rt: UringDataHandle,
+	resource: Resource,
+	sender: RuntimeWorkerChannel,
+
+	file: Fixed,
+	socket: Socket,
+
+	// only taken when dropped
+	addr: Option<Box<SockAddr>>,
+	// set while the socket is being created in the file table
+	setting_up: bool,
+}
+
+impl TcpListener {
+	/// Create a listener bound to `addr`.
+	///
+	/// If the kernel supports binding and listening through the ring, the socket is created
+	/// directly in the runtime's file table with a single chain of linked ops. It only gets a
+	/// regular fd if the port is 0, briefly, to read the port the kernel picked. Otherwise it's
+	/// created like `std::net::TcpListener::bind` and registered.
+	pub async fn bind(rt: &UringRuntime, addr: SocketAddr) -> Result<Self> {
+		let direct = rt.data.load().is_some_and(|data| {
+			[
+				opcode::Socket::CODE,
+				opcode::Bind::CODE,
+				opcode::Listen::CODE,
+			]
+			.into_iter()
+			.all(|code| data.supports(code))
+		});
+
+		if direct {
+			Self::bind_direct(rt, addr).await
+		} else {
+			Self::from_std(rt, std::net::TcpListener::bind(addr)?).await
+		}
+	}
+
+	async fn bind_direct(rt: &UringRuntime, addr: SocketAddr) -> Result<Self> {
+		let (resource, file) = Resource::register_direct(&rt.rt).await?;
+		// the listener closes the slot if anything below fails
+		let mut listener = Self {
+			rt: rt.data.clone(),
+			resource,
+			sender: rt.rt.clone(),
+			file,
+			socket: Socket::Direct(addr),
+			addr: Some(SockAddr::from_std(addr)),
+			setting_up: true,
+		};
+
+		let domain = match addr {
+			SocketAddr::V4(_) => libc::AF_INET,
+			SocketAddr::V6(_) => libc::AF_INET6,
+		};
+		let slot = DestinationSlot::try_from_slot_target(file.0)
+			.map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+		let sockaddr = listener.addr.as_ref().expect("listener is dropping");
+		let (ptr, len) = (sockaddr.as_ptr(), sockaddr.len());
+		let optlen = sockopt::socklen(size_of::<libc::c_int>());
+		// socket commands are newer than the opcode, so the option may fail on its own
+		let reuseaddr = rt
+			.data
+			.load()
+			.is_some_and(|data| data.supports(opcode::SetSockOpt::CODE));
+
+		// direct descriptors are never inherited, so there's no SOCK_CLOEXEC
+		let mut entries = vec![(
+			SOCKET_OP_ID,
+			opcode::Socket::new(domain, libc::SOCK_STREAM | libc::SOCK_NONBLOCK, 0)
+				.file_index(Some(slot))
+				.build()
+				.flags(Flags::IO_LINK),
+		)];
+		if reuseaddr {
+			// like std, so a restarted server can bind while old connections are in TIME_WAIT.
+			// hard linked, since binding doesn't depend on it
+			entries.push((
+				REUSEADDR_OP_ID,
+				sockopt::uring_entry(
+					file,
+					false,
+					libc::SOL_SOCKET,
+					libc::SO_REUSEADDR,
+					(&raw const REUSEADDR).cast_mut().cast(),
+					optlen,
+				)
+				.flags(Flags::IO_HARDLINK),
+			));
+		}
+		entries.push((
+			BIND_OP_ID,
+			opcode::Bind::new(file, ptr, len)
+				.build()
+				.flags(Flags::IO_LINK),
+		));
+		entries.push((
+			LISTEN_OP_ID,
+			opcode::Listen::new(file, libc::SOMAXCONN).build(),
+		));
+
+		let mut results: Vec<_> = entries.iter().map(|(id, _)| (*id, None)).collect();
+		let mut entries = Some(entries);
+		std::future::poll_fn(|cx| listener.poll_setup(cx, &mut entries, &mut results)).await?;
+		for (id, ret) in results {
+			if id != REUSEADDR_OP_ID
+				&& let Some(Err(err)) = ret
+			{
+				// ops after the one that failed are cancelled, so the first error is the cause
+				return Err(err);
+			}
+		}
+		listener.setting_up = false;
+
+		// the kernel picked a port, which only getsockname can tell, so borrow a regular fd
+		// for it
+		if addr.port() == 0 {
+			let entry = opcode::FixedFdInstall::new(file, 0).build();
+			// SAFETY: installing an fd doesn't reference any memory
+			let fd = unsafe { run_oneshot(rt, entry, Some(close_fd)) }.await?;
+			// SAFETY: the install returned a new fd, which we now own
+			let std =
+				std::net::TcpListener::from(unsafe { OwnedFd::from_raw_fd(fd.cast_signed()) });
+			listener.socket = Socket::Direct(std.local_addr()?);
+		}
+
+		Ok(listener)
+	}
+
+	fn start_setup(
+		&mut self,
+		cx: &mut Context<'_>,
+		entries: &[(u32, squeue::Entry)],
+	) -> Result<()> {
+		let handle = self.rt.clone();
+		let rt = handle.load().ok_or(Error::NoRuntime)?;
+		let built: Vec<_> = entries
+			.iter()
+			.map(|(id, entry)| {
+				entry.clone().user_data(
+					EventData {
+						resource: self.resource.id,
+						id: *id,
+					}
+					.into(),
+				)
+			})
+			.collect();
+
+		for (id, _) in entries {
+			self.resource.ops.prepare_submit(*id, cx);
+		}
+		// SAFETY: the address and option value outlive the ops, or are handed to the runtime if
+		// we are dropped
+		if let Err(err) = unsafe { rt.submit_all(&built) } {
+			for (id, _) in entries {
+				self.resource.ops.abort_submit(*id);
+			}
+			return Err(err);
+		}
+
+		Ok(())
+	}
+
+	fn poll_setup(
+		&mut self,
+		cx: &mut Context<'_>,
+		entries: &mut Option<Vec<(u32, squeue::Entry)>>,
+		results: &mut [(u32, Option<Result<u32>>)],
+	) -> Poll<Result<()>> {
+		if let Some(entries) = entries.take() {
+			self.start_setup(cx, &entries)?;
+		}
+
+		let mut pending = false;
+		for (id, slot) in results.iter_mut().filter(|(_, slot)| slot.is_none()) {
+			match self.resource.ops.poll_submit_id(*id, cx) {
+				Poll::Ready(Some(ret)) => *slot = Some(ret),
+				Poll::Ready(None) => unreachable!("setup op was not submitted"),
+				Poll::Pending => pending = true,
+			}
+		}
+
+		if pending {
+			Poll::Pending
+		} else {
+			Poll::Ready(Ok(()))
+		}
+	}
+
+	/// Register a listening socket with the runtime.
+	pub async fn from_std(rt: &UringRuntime, listener: std::net::TcpListener) -> Result<Self> {
+		listener.set_nonblocking(true)?;
+		Self::new(OwnedFd::from(listener), rt.data.clone(), rt.rt.clone()).await
+	}
+
+	pub(crate) async fn new(
+		fd: OwnedFd,
+		rt: UringDataHandle,
+		sender: RuntimeWorkerChannel,
+	) -> Result<Self> {
+		let raw = fd.as_raw_fd();
+		let (resource, file) = Resource::register_fd(&sender, fd).await?;
+
+		Ok(Self {
+			rt,
+			resource,
+			sender,
+			file,
+			socket: Socket::Fd(raw),
+			addr: Some(SockAddr::new()),
+			setting_up: false,
+		})
+	}
+
+	pub fn local_addr(&self) -> io::Result<SocketAddr> {
+		match self.socket {
+			Socket::Fd(fd) => {
+				// SAFETY: the fd stays open for as long as we exist, and is never closed through
+				// this
+				let std = ManuallyDrop::new(unsafe { std::net::TcpListener::from_raw_fd(fd) });
+				std.local_addr()
+			}
+			Socket::Direct(addr) => Ok(addr),
+		}
+	}
+
+	/// Accept a connection, registering it with the runtime.
+	///
+	/// If this future is dropped, the accept stays in flight and its connection is returned by
+	/// the next call instead of being lost.
+	pub async fn accept(&mut self) -> Result<(TcpStream, SocketAddr)> {
+		let (stream, addr) = std::future::poll_fn(|cx| self.poll_accept(cx)).await?;
+		let stream = TcpStream::new(stream, self.rt.clone(), self.sender.clone()).await?;
+		Ok((stream, addr))
+	}
+
+	fn poll_accept(
+		&mut self,
+		cx: &mut Context<'_>,
+	) -> Poll<Result<(std::net::TcpStream, SocketAddr)>> {
+		let this = &mut *self;
+		poll_op_impl!(ACCEPT_OP_ID, this, cx, false, {
+			Some(Ok(val)) => |val: u32| {
+				// SAFETY: accept returned a new fd, which we now own
+				let stream = std::net::TcpStream::from(unsafe { OwnedFd::from_raw_fd(val.cast_signed()) });
+				let addr = this.addr.as_ref().expect("listener is dropping").to_std()?;
+				Poll::Ready(Ok((stream, addr)))
+			},
+			None => || {
+				let addr = this.addr.as_mut().expect("listener is dropping");
+				addr.reset();
+				Ok(opcode::Accept::new(this.file, addr.as_mut_ptr(), addr.len_mut())
+					.flags(libc::SOCK_CLOEXEC)
+					.build())
+			}
+		})
+	}
+
+	/// Stream of incoming connections, accepted with a single multishot accept.
+	///
+	/// Connections the kernel already accepted are closed when the stream is dropped.
+	pub fn incoming(&mut self) -> Incoming<'_> {
+		Incoming {
+			listener: self,
+			registering: None,
+		}
+	}
+
+	fn poll_accept_multi(
+		&mut self,
+		cx: &mut Context<'_>,
+	) -> Poll<Result<(std::net::TcpStream, SocketAddr)>> {
+		let this = &mut *self;
+		poll_op_impl!(multishot ACCEPT_MULTI_OP_ID, this, cx, false, {
+			Some(Ok(val)) => |(fd, _): (u32, u32)| {
+				// SAFETY: accept returned a new fd, which we now own
+				let stream = std::net::TcpStream::from(unsafe { OwnedFd::from_raw_fd(fd.cast_signed()) });
+				let addr = stream.peer_addr()?;
+				Poll::Ready(Ok((stream, addr)))
+			},
+			None => || Ok(opcode::AcceptMulti::new(this.file).flags(libc::SOCK_CLOEXEC).build())
+		})
+	}
+}
+
+impl Drop for TcpListener {
+	fn drop(&mut self) {
+		let rt = self.rt.clone();
+		if self.setting_up {
+			// a bind in flight still reads the address, and setup ops don't return fds
+			for id in [SOCKET_OP_ID, REUSEADDR_OP_ID, BIND_OP_ID, LISTEN_OP_ID] {
+				let buf: Box<dyn Send> = if id == BIND_OP_ID {
+					Box::new(self.addr.take())
+				} else {
+					Box::new(())
+				};
+				self.resource.cancel(
+					rt.load(),
+					id,
+					OperationCancelData {
+						wake: true,
+						buf,
+						release: None,
+					},
+				);
+			}
+		} else {
+			self.resource.cancel(
+				rt.load(),
+				ACCEPT_OP_ID,
+				OperationCancelData {
+					wake: true,
+					buf: Box::new(self.addr.take()),
+					release: Some(close_fd),
+				},
+			);
+		}
+
+		let _ = self
+			.sender
+			.send(WorkerMessage::CloseResource(self.resource.dup()));
+	}
+}
+
+/// Stream of connections accepted by [`TcpListener::incoming`].
+pub struct Incoming<'a> {
+	listener: &'a mut TcpListener,
+	registering: Option<BoxFuture<'static, Result<(TcpStream, SocketAddr)>>>,
+}
+
+impl Stream for Incoming<'_> {
+	type Item = Result<(TcpStream, SocketAddr)>;
+
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+		let this = &mut *self;
+
+		if this.registering.is_none() {
+			let (stream, addr) = match ready!(this.listener.poll_accept_multi(cx)) {
+				Ok(val) => val,
+				Err(err) => return Poll::Ready(Some(Err(err))),
+			};
+
+			let (rt, sender) = (this.listener.rt.clone(), this.listener.sender.clone());
+			this.registering = Some(Box::pin(async move {
+				Ok((TcpStream::new(stream, rt, sender).await?, addr))
+			}));
+		}
+
+		let fut = this.registering.as_mut().unwrap();
+		let ret = ready!(fut.as_mut().poll(cx));
+		this.registering = None;
+
+		Poll::Ready(Some(ret))
+	}
+}
+
+impl Drop for Incoming<'_> {
+	fn drop(&mut self) {
+		let rt = self.listener.rt.clone();
+		self.listener.resource.cancel(
+			rt.load(),
+			ACCEPT_MULTI_OP_ID,
+			OperationCancelData {
+				wake: true,
+				buf: Box::new(()),
+				release: Some(close_fd),
+			},
+		);
+	}
+}
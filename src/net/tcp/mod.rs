@@ -0,0 +1,9 @@
+mod listener;
+mod socket;
+mod stream;
+
+pub use listener::{Incoming, TcpListener};
+pub use socket::TcpSocket;
+pub use stream::{
+	BorrowedReadHalf, BorrowedWriteHalf, ReadHalf, ReuniteError, TcpStream, WriteHalf,
+};
@@ -0,0 +1,144 @@
+use std::{
+	io,
+	mem::ManuallyDrop,
+	net::SocketAddr,
+	os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+};
+
+use io_uring::opcode;
+
+use crate::{
+	Result,
+	net::{addr::SockAddr, close_fd, sockopt::setsockopt},
+	rt::{UringDataHandle, UringRuntime, inner::RuntimeWorkerChannel, oneshot::run_oneshot},
+};
+
+use super::{TcpListener, TcpStream};
+
+/// A TCP socket that hasn't been connected or turned into a listener yet.
+///
+/// The socket is created through the ring, so it can be configured before connecting.
+pub struct TcpSocket {
+	rt: UringDataHandle,
+	sender: RuntimeWorkerChannel,
+	fd: OwnedFd,
+}
+
+impl TcpSocket {
+	/// Create an IPv4 socket.
+	pub async fn new_v4(rt: &UringRuntime) -> Result<Self> {
+		Self::new(rt, libc::AF_INET).await
+	}
+
+	/// Create an IPv6 socket.
+	pub async fn new_v6(rt: &UringRuntime) -> Result<Self> {
+		Self::new(rt, libc::AF_INET6).await
+	}
+
+	async fn new(rt: &UringRuntime, domain: i32) -> Result<Self> {
+		let entry = opcode::Socket::new(domain, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0).build();
+		// SAFETY: socket doesn't reference any memory
+		let fd = unsafe { run_oneshot(rt, entry, Some(close_fd)) }.await?;
+
+		Ok(Self {
+			rt: rt.data.clone(),
+			sender: rt.rt.clone(),
+			// SAFETY: socket returned a new fd, which we now own
+			fd: unsafe { OwnedFd::from_raw_fd(fd.cast_signed()) },
+		})
+	}
+
+	pub fn set_reuseaddr(&self, reuseaddr: bool) -> io::Result<()> {
+		setsockopt(
+			self.fd.as_raw_fd(),
+			libc::SOL_SOCKET,
+			libc::SO_REUSEADDR,
+			libc::c_int::from(reuseaddr),
+		)
+	}
+
+	pub fn set_reuseport(&self, reuseport: bool) -> io::Result<()> {
+		setsockopt(
+			self.fd.as_raw_fd(),
+			libc::SOL_SOCKET,
+			libc::SO_REUSEPORT,
+			libc::c_int::from(reuseport),
+		)
+	}
+
+	pub fn set_send_buffer_size(&self, size: u32) -> io::Result<()> {
+		setsockopt(
+			self.fd.as_raw_fd(),
+			libc::SOL_SOCKET,
+			libc::SO_SNDBUF,
+			size.cast_signed(),
+		)
+	}
+
+	pub fn set_recv_buffer_size(&self, size: u32) -> io::Result<()> {
+		setsockopt(
+			self.fd.as_raw_fd(),
+			libc::SOL_SOCKET,
+			libc::SO_RCVBUF,
+			size.cast_signed(),
+		)
+	}
+
+	/// Bind the socket to `addr`, for example to pick the local address before connecting.
+	pub fn bind(&self, addr: SocketAddr) -> io::Result<()> {
+		let addr = SockAddr::from_std(addr);
+		// SAFETY: addr is a valid address of the given length
+		let ret = unsafe { libc::bind(self.fd.as_raw_fd(), addr.as_ptr(), addr.len()) };
+
+		if ret == 0 {
+			Ok(())
+		} else {
+			Err(io::Error::last_os_error())
+		}
+	}
+
+	pub fn local_addr(&self) -> io::Result<SocketAddr> {
+		// SAFETY: the fd stays open for as long as we exist, and is never closed through this
+		let std =
+			ManuallyDrop::new(unsafe { std::net::TcpStream::from_raw_fd(self.fd.as_raw_fd()) });
+		std.local_addr()
+	}
+
+	/// Connect to `addr`, returning a registered stream.
+	///
+	/// Errors like `ECONNREFUSED` are returned as reported by the kernel.
+	pub async fn connect(self, addr: SocketAddr) -> Result<TcpStream> {
+		let mut stream =
+			TcpStream::new(std::net::TcpStream::from(self.fd), self.rt, self.sender).await?;
+		stream.connect_raw(SockAddr::from_std(addr)).await?;
+		Ok(stream)
+	}
+
+	/// Start listening for connections, returning a registered listener.
+	pub async fn listen(self, backlog: u32) -> Result<TcpListener> {
+		// SAFETY: the fd is a valid socket
+		let ret = unsafe {
+			libc::listen(
+				self.fd.as_raw_fd(),
+				backlog.min(i32::MAX as u32).cast_signed(),
+			)
+		};
+		if ret != 0 {
+			return Err(io::Error::last_os_error().into());
+		}
+
+		TcpListener::new(self.fd, self.rt, self.sender).await
+	}
+}
+
+impl AsFd for TcpSocket {
+	fn as_fd(&self) -> BorrowedFd<'_> {
+		self.fd.as_fd()
+	}
+}
+
+impl AsRawFd for TcpSocket {
+	fn as_raw_fd(&self) -> RawFd {
+		self.fd.as_raw_fd()
+	}
+}
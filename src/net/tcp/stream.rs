@@ -0,0 +1,297 @@
+use std::{
+	io,
+	net::SocketAddr,
+	task::{Context, Poll},
+	time::Duration,
+};
+
+use crate::{
+	Result,
+	net::{
+		sockopt::{getsockopt, setsockopt},
+		stream::{poll_send_zc, poll_sockopt, send_zc_methods, stream_types},
+	},
+	rt::{
+		Chain, UringRuntime,
+		operation::{RawSockOpt, ZeroCopyWrite},
+		owned::OwnedSockOpt,
+		resource::Resource,
+	},
+};
+
+use super::TcpSocket;
+
+stream_types!(
+	TcpStream,
+	std::net::TcpStream,
+	ReadHalf,
+	WriteHalf,
+	BorrowedReadHalf,
+	BorrowedWriteHalf,
+	ReuniteError
+);
+
+/// Addresses and socket options, the same on the stream and both of its halves.
+macro_rules! socket_methods {
+	() => {
+		fn with_std<R>(&self, f: impl FnOnce(&std::net::TcpStream) -> R) -> R {
+			// SAFETY: the fd stays open for as long as we exist, and is never closed through this
+			let std = std::mem::ManuallyDrop::new(unsafe {
+				std::os::fd::FromRawFd::from_raw_fd(self.fd)
+			});
+			f(&std)
+		}
+
+		pub fn local_addr(&self) -> io::Result<SocketAddr> {
+			self.with_std(std::net::TcpStream::local_addr)
+		}
+
+		pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+			self.with_std(std::net::TcpStream::peer_addr)
+		}
+
+		/// Disable Nagle's algorithm (`TCP_NODELAY`), so small writes go out right away.
+		pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+			self.with_std(|std| std.set_nodelay(nodelay))
+		}
+
+		pub fn nodelay(&self) -> io::Result<bool> {
+			self.with_std(std::net::TcpStream::nodelay)
+		}
+
+		/// Send keepalive probes on an idle connection (`SO_KEEPALIVE`).
+		pub fn set_keepalive(&self, keepalive: bool) -> io::Result<()> {
+			setsockopt(
+				self.fd,
+				libc::SOL_SOCKET,
+				libc::SO_KEEPALIVE,
+				libc::c_int::from(keepalive),
+			)
+		}
+
+		pub fn keepalive(&self) -> io::Result<bool> {
+			getsockopt::<libc::c_int>(self.fd, libc::SOL_SOCKET, libc::SO_KEEPALIVE)
+				.map(|val| val != 0)
+		}
+
+		/// Make closing the socket wait up to `linger` for unsent data to go out (`SO_LINGER`).
+		/// With a zero duration the connection is reset on close instead.
+		pub fn set_linger(&self, linger: Option<Duration>) -> io::Result<()> {
+			setsockopt(
+				self.fd,
+				libc::SOL_SOCKET,
+				libc::SO_LINGER,
+				libc::linger {
+					l_onoff: libc::c_int::from(linger.is_some()),
+					l_linger: linger.map_or(0, |linger| {
+						linger.as_secs().try_into().unwrap_or(libc::c_int::MAX)
+					}),
+				},
+			)
+		}
+
+		pub fn linger(&self) -> io::Result<Option<Duration>> {
+			let linger: libc::linger = getsockopt(self.fd, libc::SOL_SOCKET, libc::SO_LINGER)?;
+			Ok((linger.l_onoff != 0)
+				.then(|| Duration::from_secs(linger.l_linger.try_into().unwrap_or(0))))
+		}
+
+		/// Time to live of outgoing packets (`IP_TTL`).
+		pub fn set_ttl(&self, ttl: u32) -> io::Result<()> {
+			self.with_std(|std| std.set_ttl(ttl))
+		}
+
+		pub fn ttl(&self) -> io::Result<u32> {
+			self.with_std(std::net::TcpStream::ttl)
+		}
+
+		/// Take the pending error on the socket (`SO_ERROR`), if any.
+		pub fn take_error(&self) -> io::Result<Option<io::Error>> {
+			self.with_std(std::net::TcpStream::take_error)
+		}
+	};
+}
+
+/// Socket options set through the ring after buffered writes went out, so they are ordered with
+/// the sends around them. Only on the stream and its write halves, since they use the write op.
+///
+/// Kernels without socket commands fall back to plain `setsockopt`/`getsockopt`.
+macro_rules! ordered_socket_methods {
+	() => {
+		async fn set_option(&mut self, level: i32, name: i32, val: u32) -> io::Result<()> {
+			let val = libc::c_int::try_from(val).map_err(|_| io::ErrorKind::InvalidInput)?;
+			OwnedSockOpt::set(self, level, name, val).await.map(|_| ())
+		}
+
+		async fn option(&mut self, level: i32, name: i32) -> io::Result<u32> {
+			let val: libc::c_int = OwnedSockOpt::get(self, level, name, 0).await?;
+			u32::try_from(val).map_err(|_| io::ErrorKind::InvalidData.into())
+		}
+
+		/// Hold back partial frames until the stream is uncorked (`TCP_CORK`), for example while
+		/// a response is written in pieces. Uncorking sends what was held back.
+		pub async fn set_cork(&mut self, cork: bool) -> io::Result<()> {
+			self.set_option(libc::IPPROTO_TCP, libc::TCP_CORK, cork.into())
+				.await
+		}
+
+		pub async fn cork(&mut self) -> io::Result<bool> {
+			Ok(self.option(libc::IPPROTO_TCP, libc::TCP_CORK).await? != 0)
+		}
+
+		/// Size of the kernel's send buffer (`SO_SNDBUF`). The kernel doubles it to leave room
+		/// for bookkeeping, which the getter includes.
+		pub async fn set_send_buffer_size(&mut self, size: u32) -> io::Result<()> {
+			self.set_option(libc::SOL_SOCKET, libc::SO_SNDBUF, size)
+				.await
+		}
+
+		pub async fn send_buffer_size(&mut self) -> io::Result<u32> {
+			self.option(libc::SOL_SOCKET, libc::SO_SNDBUF).await
+		}
+
+		/// Size of the kernel's receive buffer (`SO_RCVBUF`), doubled like the send buffer.
+		pub async fn set_recv_buffer_size(&mut self, size: u32) -> io::Result<()> {
+			self.set_option(libc::SOL_SOCKET, libc::SO_RCVBUF, size)
+				.await
+		}
+
+		pub async fn recv_buffer_size(&mut self) -> io::Result<u32> {
+			self.option(libc::SOL_SOCKET, libc::SO_RCVBUF).await
+		}
+
+		/// Steer the connection's packets to `cpu` (`SO_INCOMING_CPU`).
+		pub async fn set_incoming_cpu(&mut self, cpu: u32) -> io::Result<()> {
+			self.set_option(libc::SOL_SOCKET, libc::SO_INCOMING_CPU, cpu)
+				.await
+		}
+
+		pub async fn incoming_cpu(&mut self) -> io::Result<u32> {
+			self.option(libc::SOL_SOCKET, libc::SO_INCOMING_CPU).await
+		}
+	};
+}
+
+impl TcpStream {
+	/// Open a connection to `addr`.
+	pub async fn connect(rt: &UringRuntime, addr: SocketAddr) -> Result<Self> {
+		let socket = match addr {
+			SocketAddr::V4(_) => TcpSocket::new_v4(rt).await?,
+			SocketAddr::V6(_) => TcpSocket::new_v6(rt).await?,
+		};
+		socket.connect(addr).await
+	}
+
+	/// Start building a chain of linked operations that read and write this stream, borrowing
+	/// it until the chain completes.
+	pub fn chain(&mut self) -> Chain<&mut Self> {
+		Chain::new(self.rt.clone(), self.sender.clone(), self)
+	}
+
+	/// Ops of the stream, for chains that run on them.
+	pub(crate) fn resource_mut(&mut self) -> &mut Resource {
+		&mut self.resource
+	}
+
+	socket_methods!();
+	ordered_socket_methods!();
+	send_zc_methods!();
+}
+
+impl ReadHalf {
+	socket_methods!();
+}
+
+impl WriteHalf {
+	socket_methods!();
+	ordered_socket_methods!();
+	send_zc_methods!();
+}
+
+impl BorrowedReadHalf<'_> {
+	socket_methods!();
+}
+
+impl BorrowedWriteHalf<'_> {
+	socket_methods!();
+	ordered_socket_methods!();
+	send_zc_methods!();
+}
+
+impl ZeroCopyWrite for TcpStream {
+	unsafe fn poll_send_zc(
+		&mut self,
+		cx: &mut Context<'_>,
+		ptr: *const u8,
+		len: usize,
+		buf_index: Option<u16>,
+	) -> Poll<Result<usize>> {
+		poll_send_zc!(self, cx, ptr, len, buf_index);
+	}
+}
+
+impl ZeroCopyWrite for WriteHalf {
+	unsafe fn poll_send_zc(
+		&mut self,
+		cx: &mut Context<'_>,
+		ptr: *const u8,
+		len: usize,
+		buf_index: Option<u16>,
+	) -> Poll<Result<usize>> {
+		poll_send_zc!(self, cx, ptr, len, buf_index);
+	}
+}
+
+impl ZeroCopyWrite for BorrowedWriteHalf<'_> {
+	unsafe fn poll_send_zc(
+		&mut self,
+		cx: &mut Context<'_>,
+		ptr: *const u8,
+		len: usize,
+		buf_index: Option<u16>,
+	) -> Poll<Result<usize>> {
+		poll_send_zc!(self, cx, ptr, len, buf_index);
+	}
+}
+
+impl RawSockOpt for TcpStream {
+	unsafe fn poll_sockopt(
+		&mut self,
+		cx: &mut Context<'_>,
+		get: bool,
+		level: i32,
+		name: i32,
+		val: *mut libc::c_void,
+		len: u32,
+	) -> Poll<Result<usize>> {
+		poll_sockopt!(self, cx, get, level, name, val, len);
+	}
+}
+
+impl RawSockOpt for WriteHalf {
+	unsafe fn poll_sockopt(
+		&mut self,
+		cx: &mut Context<'_>,
+		get: bool,
+		level: i32,
+		name: i32,
+		val: *mut libc::c_void,
+		len: u32,
+	) -> Poll<Result<usize>> {
+		poll_sockopt!(self, cx, get, level, name, val, len);
+	}
+}
+
+impl RawSockOpt for BorrowedWriteHalf<'_> {
+	unsafe fn poll_sockopt(
+		&mut self,
+		cx: &mut Context<'_>,
+		get: bool,
+		level: i32,
+		name: i32,
+		val: *mut libc::c_void,
+		len: u32,
+	) -> Poll<Result<usize>> {
+		poll_sockopt!(self, cx, get, level, name, val, len);
+	}
+}
@@ -0,0 +1,268 @@
+use std::{
+	io,
+	mem::ManuallyDrop,
+	net::SocketAddr,
+	os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+	task::{Context, Poll},
+};
+
+use futures::ready;
+use io_uring::{opcode, types::Fixed};
+
+use crate::{
+	Result,
+	buf::{IoBuf, IoBufMut},
+	rt::{
+		UringDataHandle, UringRuntime,
+		inner::{RuntimeWorkerChannel, WorkerMessage},
+		operation::{OperationCancelData, OperationPollState, ProtectedOps, poll_op_impl},
+		resource::Resource,
+	},
+};
+
+use super::{
+	addr::SockAddr,
+	msg::{MsgIo, MsgZcIo, RecvMsg, SendMsg, cmsg_space},
+	sockopt::setsockopt,
+};
+
+const SEND_OP_ID: u32 = 0;
+const RECV_OP_ID: u32 = 1;
+
+pub struct UdpSocket {
+	rt: UringDataHandle,
+	resource: Resource,
+	sender: RuntimeWorkerChannel,
+
+	file: Fixed,
+	// owned by the runtime, which keeps it open until the resource is removed
+	fd: RawFd,
+}
+
+impl UdpSocket {
+	/// Create a socket bound to `addr`.
+	pub async fn bind(rt: &UringRuntime, addr: SocketAddr) -> Result<Self> {
+		Self::from_std(rt, std::net::UdpSocket::bind(addr)?).await
+	}
+
+	/// Register a socket with the runtime.
+	pub async fn from_std(rt: &UringRuntime, socket: std::net::UdpSocket) -> Result<Self> {
+		socket.set_nonblocking(true)?;
+		let fd = OwnedFd::from(socket);
+		let raw = fd.as_raw_fd();
+		let (resource, file) = Resource::register_fd(&rt.rt, fd).await?;
+
+		Ok(Self {
+			rt: rt.data.clone(),
+			resource,
+			sender: rt.rt.clone(),
+			file,
+			fd: raw,
+		})
+	}
+
+	fn with_std<R>(&self, f: impl FnOnce(&std::net::UdpSocket) -> R) -> R {
+		// SAFETY: the fd stays open for as long as we exist, and is never closed through this
+		let std = ManuallyDrop::new(unsafe { std::net::UdpSocket::from_raw_fd(self.fd) });
+		f(&std)
+	}
+
+	pub fn local_addr(&self) -> io::Result<SocketAddr> {
+		self.with_std(std::net::UdpSocket::local_addr)
+	}
+
+	pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+		self.with_std(std::net::UdpSocket::peer_addr)
+	}
+
+	/// Set the default destination of `send`, and only receive datagrams from `addr`.
+	pub fn connect(&self, addr: SocketAddr) -> io::Result<()> {
+		self.with_std(|std| std.connect(addr))
+	}
+
+	/// Send the initialized part of an owned buffer to the connected address.
+	pub async fn send<B: IoBuf>(&mut self, buf: B) -> (io::Result<usize>, B) {
+		SendMsg::new(self, buf, None).await
+	}
+
+	/// Send the initialized part of an owned buffer to `addr`.
+	pub async fn send_to<B: IoBuf>(&mut self, buf: B, addr: SocketAddr) -> (io::Result<usize>, B) {
+		SendMsg::new(self, buf, Some(SockAddr::from_std(addr))).await
+	}
+
+	/// Receive a datagram into an owned buffer, returning it once the receive completes.
+	pub async fn recv<B: IoBufMut>(&mut self, buf: B) -> (io::Result<usize>, B) {
+		let (ret, buf) = RecvMsg::new(self, buf).await;
+		(ret.map(|(cnt, _)| cnt), buf)
+	}
+
+	/// Receive a datagram into an owned buffer along with the address it came from.
+	pub async fn recv_from<B: IoBufMut>(&mut self, buf: B) -> (io::Result<(usize, SocketAddr)>, B) {
+		let (ret, buf) = RecvMsg::new(self, buf).await;
+		(ret.and_then(|(cnt, msg)| Ok((cnt, msg.addr()?))), buf)
+	}
+
+	/// Send the initialized part of an owned buffer to the connected address as datagrams of
+	/// `segment_size` bytes each, split up by the kernel (`UDP_SEGMENT`). The last datagram may
+	/// be shorter.
+	pub async fn send_segmented<B: IoBuf>(
+		&mut self,
+		buf: B,
+		segment_size: u16,
+	) -> (io::Result<usize>, B) {
+		SendMsg::new(self, buf, None)
+			.cmsg(
+				libc::SOL_UDP,
+				libc::UDP_SEGMENT,
+				&segment_size.to_ne_bytes(),
+			)
+			.await
+	}
+
+	/// Like [`send_segmented`](Self::send_segmented), but sends to `addr`.
+	pub async fn send_to_segmented<B: IoBuf>(
+		&mut self,
+		buf: B,
+		segment_size: u16,
+		addr: SocketAddr,
+	) -> (io::Result<usize>, B) {
+		SendMsg::new(self, buf, Some(SockAddr::from_std(addr)))
+			.cmsg(
+				libc::SOL_UDP,
+				libc::UDP_SEGMENT,
+				&segment_size.to_ne_bytes(),
+			)
+			.await
+	}
+
+	/// Send the initialized part of an owned buffer to the connected address without copying it
+	/// into the kernel (`SendMsgZc`), returning it once the kernel no longer references it.
+	///
+	/// This waits for the send's notification rather than just its result, so it only pays off
+	/// for large buffers, like segmented sends.
+	pub async fn send_zc<B: IoBuf>(&mut self, buf: B) -> (io::Result<usize>, B) {
+		SendMsg::new(self, buf, None).zerocopy().await
+	}
+
+	/// Like [`send_zc`](Self::send_zc), but sends to `addr`.
+	pub async fn send_to_zc<B: IoBuf>(
+		&mut self,
+		buf: B,
+		addr: SocketAddr,
+	) -> (io::Result<usize>, B) {
+		SendMsg::new(self, buf, Some(SockAddr::from_std(addr)))
+			.zerocopy()
+			.await
+	}
+
+	/// Let the kernel coalesce received datagrams from the same flow (`UDP_GRO`), see
+	/// [`recv_from_segmented`](Self::recv_from_segmented).
+	pub fn set_gro(&self, gro: bool) -> io::Result<()> {
+		setsockopt(
+			self.fd,
+			libc::SOL_UDP,
+			libc::UDP_GRO,
+			libc::c_int::from(gro),
+		)
+	}
+
+	/// Receive one or more datagrams into an owned buffer, returning the total length, the
+	/// address they came from and the size of each datagram.
+	///
+	/// With [`set_gro`](Self::set_gro) enabled, the kernel may coalesce several datagrams of the
+	/// same size into one buffer, only the last of which may be shorter. Otherwise the segment
+	/// size is the length of the single datagram received.
+	pub async fn recv_from_segmented<B: IoBufMut>(
+		&mut self,
+		buf: B,
+	) -> (io::Result<(usize, SocketAddr, usize)>, B) {
+		let (ret, buf) = RecvMsg::new(self, buf)
+			.control(cmsg_space(size_of::<libc::c_int>()))
+			.await;
+
+		let ret = ret.and_then(|(cnt, msg)| {
+			let segment_size = msg
+				.cmsgs()
+				.find(|(level, ty, _)| *level == libc::SOL_UDP && *ty == libc::UDP_GRO)
+				.and_then(|(_, _, data)| Some(libc::c_int::from_ne_bytes(data.try_into().ok()?)))
+				.and_then(|size| usize::try_from(size).ok())
+				.unwrap_or(cnt);
+			Ok((cnt, msg.addr()?, segment_size))
+		});
+		(ret, buf)
+	}
+}
+
+impl ProtectedOps for UdpSocket {
+	const READ_OP_ID: u32 = RECV_OP_ID;
+	const WRITE_OP_ID: u32 = SEND_OP_ID;
+
+	fn get_rt(&self) -> &UringDataHandle {
+		&self.rt
+	}
+
+	fn cancel_op(&mut self, id: u32, data: OperationCancelData) {
+		self.resource.cancel(self.rt.load(), id, data);
+	}
+
+	fn detach_op(&mut self, id: u32, data: OperationCancelData) {
+		let _ = self.resource.detach(self.rt.load(), id, data);
+	}
+
+	fn submission(&self, id: u32) -> OperationPollState {
+		self.resource.ops.submission(id)
+	}
+
+	fn interrupt_op(&self, id: u32) {
+		self.resource.interrupt(self.rt.load(), id);
+	}
+}
+
+impl MsgIo for UdpSocket {
+	unsafe fn poll_send_msg(
+		&mut self,
+		cx: &mut Context<'_>,
+		msg: *const libc::msghdr,
+	) -> Poll<Result<u32>> {
+		let this = self;
+		poll_op_impl!(SEND_OP_ID, this, cx, false, {
+			Some(Ok(val)) => |val| Poll::Ready(Ok(val)),
+			None => || Ok(opcode::SendMsg::new(this.file, msg).build())
+		})
+	}
+
+	unsafe fn poll_recv_msg(
+		&mut self,
+		cx: &mut Context<'_>,
+		msg: *mut libc::msghdr,
+		flags: u32,
+	) -> Poll<Result<u32>> {
+		let this = self;
+		poll_op_impl!(RECV_OP_ID, this, cx, false, {
+			Some(Ok(val)) => |val| Poll::Ready(Ok(val)),
+			None => || Ok(opcode::RecvMsg::new(this.file, msg).flags(flags).build())
+		})
+	}
+}
+
+impl MsgZcIo for UdpSocket {
+	unsafe fn poll_send_msg_zc(
+		&mut self,
+		cx: &mut Context<'_>,
+		msg: *const libc::msghdr,
+	) -> Poll<Result<u32>> {
+		let this = self;
+		poll_op_impl!(zerocopy SEND_OP_ID, this, cx, false, {
+			Some(Ok(val)) => |val| Poll::Ready(Ok(val)),
+			None => || Ok(opcode::SendMsgZc::new(this.file, msg).build())
+		})
+	}
+}
+
+impl Drop for UdpSocket {
+	fn drop(&mut self) {
+		let _ = self
+			.sender
+			.send(WorkerMessage::CloseResource(self.resource.dup()));
+	}
+}
@@ -0,0 +1,298 @@
+use std::{
+	io,
+	mem::ManuallyDrop,
+	os::{
+		fd::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+		unix::net::SocketAddr,
+	},
+	path::Path,
+	task::{Context, Poll},
+};
+
+use futures::ready;
+use io_uring::{opcode, types::Fixed};
+
+use crate::{
+	Result,
+	buf::{IoBuf, IoBufMut},
+	net::{
+		addr::SockAddr,
+		msg::{MsgIo, RecvMsg, SendMsg, cmsg_space},
+		sockopt::setsockopt,
+	},
+	rt::{
+		UringDataHandle, UringRuntime,
+		inner::{RuntimeWorkerChannel, WorkerMessage},
+		operation::{OperationCancelData, OperationPollState, ProtectedOps, poll_op_impl},
+		resource::Resource,
+	},
+};
+
+use super::{UCred, dup_fds};
+
+const SEND_OP_ID: u32 = 0;
+const RECV_OP_ID: u32 = 1;
+
+pub struct UnixDatagram {
+	rt: UringDataHandle,
+	resource: Resource,
+	sender: RuntimeWorkerChannel,
+
+	file: Fixed,
+	// owned by the runtime, which keeps it open until the resource is removed
+	fd: RawFd,
+}
+
+impl UnixDatagram {
+	/// Create a socket bound to `path`.
+	pub async fn bind(rt: &UringRuntime, path: impl AsRef<Path>) -> Result<Self> {
+		Self::from_std(rt, std::os::unix::net::UnixDatagram::bind(path)?).await
+	}
+
+	/// Create a socket bound to `addr`, which can also be in the abstract namespace (see
+	/// [`SocketAddrExt`](std::os::linux::net::SocketAddrExt)).
+	pub async fn bind_addr(rt: &UringRuntime, addr: &SocketAddr) -> Result<Self> {
+		Self::from_std(rt, std::os::unix::net::UnixDatagram::bind_addr(addr)?).await
+	}
+
+	/// Create a socket that isn't bound to an address, so it can send but not be replied to.
+	pub async fn unbound(rt: &UringRuntime) -> Result<Self> {
+		Self::from_std(rt, std::os::unix::net::UnixDatagram::unbound()?).await
+	}
+
+	/// Create a pair of connected sockets.
+	pub async fn pair(rt: &UringRuntime) -> Result<(Self, Self)> {
+		let (a, b) = std::os::unix::net::UnixDatagram::pair()?;
+		Ok((Self::from_std(rt, a).await?, Self::from_std(rt, b).await?))
+	}
+
+	/// Register a socket with the runtime.
+	pub async fn from_std(
+		rt: &UringRuntime,
+		socket: std::os::unix::net::UnixDatagram,
+	) -> Result<Self> {
+		socket.set_nonblocking(true)?;
+		let fd = OwnedFd::from(socket);
+		let raw = fd.as_raw_fd();
+		let (resource, file) = Resource::register_fd(&rt.rt, fd).await?;
+
+		Ok(Self {
+			rt: rt.data.clone(),
+			resource,
+			sender: rt.rt.clone(),
+			file,
+			fd: raw,
+		})
+	}
+
+	fn with_std<R>(&self, f: impl FnOnce(&std::os::unix::net::UnixDatagram) -> R) -> R {
+		// SAFETY: the fd stays open for as long as we exist, and is never closed through this
+		let std =
+			ManuallyDrop::new(unsafe { std::os::unix::net::UnixDatagram::from_raw_fd(self.fd) });
+		f(&std)
+	}
+
+	pub fn local_addr(&self) -> io::Result<SocketAddr> {
+		self.with_std(std::os::unix::net::UnixDatagram::local_addr)
+	}
+
+	pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+		self.with_std(std::os::unix::net::UnixDatagram::peer_addr)
+	}
+
+	/// Credentials of the process on the other end, from when the socket was connected.
+	pub fn peer_cred(&self) -> io::Result<UCred> {
+		super::peer_cred(self.fd)
+	}
+
+	/// Set the default destination of `send` to the socket bound to `path`, and only receive
+	/// datagrams from it.
+	pub fn connect(&self, path: impl AsRef<Path>) -> io::Result<()> {
+		self.with_std(|std| std.connect(path))
+	}
+
+	/// Like [`connect`](Self::connect), but to `addr`, which can also be in the abstract
+	/// namespace.
+	pub fn connect_addr(&self, addr: &SocketAddr) -> io::Result<()> {
+		self.with_std(|std| std.connect_addr(addr))
+	}
+
+	/// Send the initialized part of an owned buffer to the connected address.
+	pub async fn send<B: IoBuf>(&mut self, buf: B) -> (io::Result<usize>, B) {
+		SendMsg::new(self, buf, None).await
+	}
+
+	/// Send the initialized part of an owned buffer to the socket bound to `path`.
+	pub async fn send_to<B: IoBuf>(
+		&mut self,
+		buf: B,
+		path: impl AsRef<Path>,
+	) -> (io::Result<usize>, B) {
+		match SocketAddr::from_pathname(path) {
+			Ok(addr) => self.send_to_addr(buf, &addr).await,
+			Err(err) => (Err(err), buf),
+		}
+	}
+
+	/// Like [`send_to`](Self::send_to), but to `addr`, which can also be in the abstract
+	/// namespace.
+	pub async fn send_to_addr<B: IoBuf>(
+		&mut self,
+		buf: B,
+		addr: &SocketAddr,
+	) -> (io::Result<usize>, B) {
+		match SockAddr::from_unix(addr) {
+			Ok(addr) => SendMsg::new(self, buf, Some(addr)).await,
+			Err(err) => (Err(err), buf),
+		}
+	}
+
+	/// Receive a datagram into an owned buffer, returning it once the receive completes.
+	pub async fn recv<B: IoBufMut>(&mut self, buf: B) -> (io::Result<usize>, B) {
+		let (ret, buf) = RecvMsg::new(self, buf).await;
+		(ret.map(|(cnt, _)| cnt), buf)
+	}
+
+	/// Receive a datagram into an owned buffer along with the address it came from, `None` if
+	/// the sender isn't bound.
+	pub async fn recv_from<B: IoBufMut>(
+		&mut self,
+		buf: B,
+	) -> (io::Result<(usize, Option<SocketAddr>)>, B) {
+		let (ret, buf) = RecvMsg::new(self, buf).await;
+		(
+			ret.and_then(|(cnt, mut msg)| Ok((cnt, msg.unix_addr()?))),
+			buf,
+		)
+	}
+
+	/// Send the initialized part of an owned buffer to the connected address along with `fds`
+	/// (`SCM_RIGHTS`). The fds are duplicated, so the caller can close its own copies right away.
+	pub async fn send_with_fds<B: IoBuf>(
+		&mut self,
+		buf: B,
+		fds: &[BorrowedFd<'_>],
+	) -> (io::Result<usize>, B) {
+		let fds = match dup_fds(fds) {
+			Ok(fds) => fds,
+			Err(err) => return (Err(err), buf),
+		};
+		SendMsg::new(self, buf, None).fds(fds).await
+	}
+
+	/// Receive a datagram into an owned buffer, along with up to `max_fds` fds passed with it.
+	/// Further fds are closed by the kernel.
+	///
+	/// The fds are returned close-on-exec; register them with the runtime through `from_std` of
+	/// the matching type. If this future is dropped before the receive completes, any fds
+	/// received in the meantime are closed.
+	pub async fn recv_with_fds<B: IoBufMut>(
+		&mut self,
+		buf: B,
+		max_fds: usize,
+	) -> (io::Result<(usize, Vec<OwnedFd>)>, B) {
+		let (ret, buf) = RecvMsg::new(self, buf).fds(max_fds).await;
+		(ret.map(|(cnt, mut msg)| (cnt, msg.take_fds())), buf)
+	}
+
+	/// Have the kernel attach the sender's credentials to every received datagram
+	/// (`SO_PASSCRED`), see [`recv_with_cred`](Self::recv_with_cred).
+	pub fn set_passcred(&self, passcred: bool) -> io::Result<()> {
+		setsockopt(
+			self.fd,
+			libc::SOL_SOCKET,
+			libc::SO_PASSCRED,
+			libc::c_int::from(passcred),
+		)
+	}
+
+	/// Send the initialized part of an owned buffer to the connected address along with `cred`
+	/// (`SCM_CREDENTIALS`), which the receiver gets with
+	/// [`recv_with_cred`](Self::recv_with_cred).
+	///
+	/// The kernel attaches our credentials anyway if the receiver set `SO_PASSCRED`, this sends
+	/// others the process is allowed to claim, see [`UCred::new`].
+	pub async fn send_with_cred<B: IoBuf>(
+		&mut self,
+		buf: B,
+		cred: UCred,
+	) -> (io::Result<usize>, B) {
+		SendMsg::new(self, buf, None).cred(cred.to_raw()).await
+	}
+
+	/// Receive a datagram into an owned buffer, along with the credentials of its sender
+	/// (`SCM_CREDENTIALS`). These are only attached with [`set_passcred`](Self::set_passcred)
+	/// enabled.
+	pub async fn recv_with_cred<B: IoBufMut>(
+		&mut self,
+		buf: B,
+	) -> (io::Result<(usize, Option<UCred>)>, B) {
+		let (ret, buf) = RecvMsg::new(self, buf)
+			.control(cmsg_space(size_of::<libc::ucred>()))
+			.await;
+		(
+			ret.map(|(cnt, msg)| (cnt, msg.cred().map(UCred::from_raw))),
+			buf,
+		)
+	}
+}
+
+impl ProtectedOps for UnixDatagram {
+	const READ_OP_ID: u32 = RECV_OP_ID;
+	const WRITE_OP_ID: u32 = SEND_OP_ID;
+
+	fn get_rt(&self) -> &UringDataHandle {
+		&self.rt
+	}
+
+	fn cancel_op(&mut self, id: u32, data: OperationCancelData) {
+		self.resource.cancel(self.rt.load(), id, data);
+	}
+
+	fn detach_op(&mut self, id: u32, data: OperationCancelData) {
+		let _ = self.resource.detach(self.rt.load(), id, data);
+	}
+
+	fn submission(&self, id: u32) -> OperationPollState {
+		self.resource.ops.submission(id)
+	}
+
+	fn interrupt_op(&self, id: u32) {
+		self.resource.interrupt(self.rt.load(), id);
+	}
+}
+
+impl MsgIo for UnixDatagram {
+	unsafe fn poll_send_msg(
+		&mut self,
+		cx: &mut Context<'_>,
+		msg: *const libc::msghdr,
+	) -> Poll<Result<u32>> {
+		let this = self;
+		poll_op_impl!(SEND_OP_ID, this, cx, false, {
+			Some(Ok(val)) => |val| Poll::Ready(Ok(val)),
+			None => || Ok(opcode::SendMsg::new(this.file, msg).build())
+		})
+	}
+
+	unsafe fn poll_recv_msg(
+		&mut self,
+		cx: &mut Context<'_>,
+		msg: *mut libc::msghdr,
+		flags: u32,
+	) -> Poll<Result<u32>> {
+		let this = self;
+		poll_op_impl!(RECV_OP_ID, this, cx, false, {
+			Some(Ok(val)) => |val| Poll::Ready(Ok(val)),
+			None => || Ok(opcode::RecvMsg::new(this.file, msg).flags(flags).build())
+		})
+	}
+}
+
+impl Drop for UnixDatagram {
+	fn drop(&mut self) {
+		let _ = self
+			.sender
+			.send(WorkerMessage::CloseResource(self.resource.dup()));
+	}
+}
@@ -0,0 +1,124 @@
+use std::{
+	io,
+	mem::ManuallyDrop,
+	os::{
+		fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+		unix::net::SocketAddr,
+	},
+	path::Path,
+	task::{Context, Poll},
+};
+
+use futures::ready;
+use io_uring::{opcode, types::Fixed};
+
+use crate::{
+	Result,
+	net::close_fd,
+	rt::{
+		UringDataHandle, UringRuntime,
+		inner::{RuntimeWorkerChannel, WorkerMessage},
+		operation::{OperationCancelData, poll_op_impl},
+		resource::Resource,
+	},
+};
+
+use super::UnixStream;
+
+const ACCEPT_OP_ID: u32 = 0;
+
+pub struct UnixListener {
+	rt: UringDataHandle,
+	resource: Resource,
+	sender: RuntimeWorkerChannel,
+
+	file: Fixed,
+	// owned by the runtime, which keeps it open until the resource is removed
+	fd: RawFd,
+}
+
+impl UnixListener {
+	/// Create a listener bound to `path`.
+	pub async fn bind(rt: &UringRuntime, path: impl AsRef<Path>) -> Result<Self> {
+		Self::from_std(rt, std::os::unix::net::UnixListener::bind(path)?).await
+	}
+
+	/// Create a listener bound to `addr`, which can also be in the abstract namespace (see
+	/// [`SocketAddrExt`](std::os::linux::net::SocketAddrExt)).
+	pub async fn bind_addr(rt: &UringRuntime, addr: &SocketAddr) -> Result<Self> {
+		Self::from_std(rt, std::os::unix::net::UnixListener::bind_addr(addr)?).await
+	}
+
+	/// Register a listening socket with the runtime.
+	pub async fn from_std(
+		rt: &UringRuntime,
+		listener: std::os::unix::net::UnixListener,
+	) -> Result<Self> {
+		listener.set_nonblocking(true)?;
+		let fd = OwnedFd::from(listener);
+		let raw = fd.as_raw_fd();
+		let (resource, file) = Resource::register_fd(&rt.rt, fd).await?;
+
+		Ok(Self {
+			rt: rt.data.clone(),
+			resource,
+			sender: rt.rt.clone(),
+			file,
+			fd: raw,
+		})
+	}
+
+	pub fn local_addr(&self) -> io::Result<SocketAddr> {
+		// SAFETY: the fd stays open for as long as we exist, and is never closed through this
+		let std =
+			ManuallyDrop::new(unsafe { std::os::unix::net::UnixListener::from_raw_fd(self.fd) });
+		std.local_addr()
+	}
+
+	/// Accept a connection, registering it with the runtime.
+	///
+	/// If this future is dropped, the accept stays in flight and its connection is returned by
+	/// the next call instead of being lost.
+	pub async fn accept(&mut self) -> Result<(UnixStream, SocketAddr)> {
+		let stream = std::future::poll_fn(|cx| self.poll_accept(cx)).await?;
+		let addr = stream.peer_addr()?;
+		let stream = UnixStream::new(stream, self.rt.clone(), self.sender.clone()).await?;
+		Ok((stream, addr))
+	}
+
+	fn poll_accept(
+		&mut self,
+		cx: &mut Context<'_>,
+	) -> Poll<Result<std::os::unix::net::UnixStream>> {
+		let this = &mut *self;
+		poll_op_impl!(ACCEPT_OP_ID, this, cx, false, {
+			Some(Ok(val)) => |val: u32| {
+				// SAFETY: accept returned a new fd, which we now own
+				let fd = unsafe { OwnedFd::from_raw_fd(val.cast_signed()) };
+				Poll::Ready(Ok(std::os::unix::net::UnixStream::from(fd)))
+			},
+			None => || Ok(opcode::Accept::new(this.file, std::ptr::null_mut(), std::ptr::null_mut())
+				.flags(libc::SOCK_CLOEXEC)
+				.build())
+		})
+	}
+}
+
+impl Drop for UnixListener {
+	fn drop(&mut self) {
+		let rt = self.rt.clone();
+		self.resource.cancel(
+			rt.load(),
+			ACCEPT_OP_ID,
+			OperationCancelData {
+				wake: true,
+				buf: Box::new(()),
+				release: Some(close_fd),
+			},
+		);
+
+		let _ = self
+			.sender
+			.send(WorkerMessage::CloseResource(self.resource.dup()));
+	}
+}
@@ -0,0 +1,76 @@
+mod datagram;
+mod listener;
+mod stream;
+
+pub use datagram::UnixDatagram;
+pub use listener::UnixListener;
+pub use stream::{
+	BorrowedReadHalf, BorrowedWriteHalf, ReadHalf, ReuniteError, UnixStream, WriteHalf,
+};
+
+use std::{
+	io,
+	os::fd::{BorrowedFd, OwnedFd, RawFd},
+};
+
+use super::sockopt::getsockopt;
+
+/// Credentials of the process on the other end of a Unix socket.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UCred {
+	pid: libc::pid_t,
+	uid: libc::uid_t,
+	gid: libc::gid_t,
+}
+
+impl UCred {
+	/// Credentials to send with `send_with_cred`. Without `CAP_SYS_ADMIN`, `CAP_SETUID` and
+	/// `CAP_SETGID`, the kernel only accepts our own, see [`current`](Self::current).
+	pub fn new(pid: libc::pid_t, uid: libc::uid_t, gid: libc::gid_t) -> Self {
+		Self { pid, uid, gid }
+	}
+
+	/// Credentials of this process, with its effective uid and gid.
+	pub fn current() -> Self {
+		// SAFETY: these calls can't fail
+		unsafe { Self::new(libc::getpid(), libc::geteuid(), libc::getegid()) }
+	}
+
+	pub(crate) fn from_raw(cred: libc::ucred) -> Self {
+		Self {
+			pid: cred.pid,
+			uid: cred.uid,
+			gid: cred.gid,
+		}
+	}
+
+	pub(crate) fn to_raw(self) -> libc::ucred {
+		libc::ucred {
+			pid: self.pid,
+			uid: self.uid,
+			gid: self.gid,
+		}
+	}
+
+	pub fn pid(&self) -> libc::pid_t {
+		self.pid
+	}
+
+	pub fn uid(&self) -> libc::uid_t {
+		self.uid
+	}
+
+	pub fn gid(&self) -> libc::gid_t {
+		self.gid
+	}
+}
+
+pub(crate) fn peer_cred(fd: RawFd) -> io::Result<UCred> {
+	let cred: libc::ucred = getsockopt(fd, libc::SOL_SOCKET, libc::SO_PEERCRED)?;
+	Ok(UCred::from_raw(cred))
+}
+
+/// Duplicate `fds` so they stay open until a send passing them completes.
+pub(crate) fn dup_fds(fds: &[BorrowedFd<'_>]) -> io::Result<Vec<OwnedFd>> {
+	fds.iter().map(BorrowedFd::try_clone_to_owned).collect()
+}
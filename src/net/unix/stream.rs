@@ -0,0 +1,213 @@
+use std::{
+	io,
+	mem::ManuallyDrop,
+	os::{
+		fd::{BorrowedFd, FromRawFd, OwnedFd},
+		unix::net::SocketAddr,
+	},
+	path::Path,
+	task::{Context, Poll},
+};
+
+use futures::ready;
+use io_uring::opcode;
+
+use crate::{
+	Error, Result,
+	buf::{IoBuf, IoBufMut},
+	compat::{ReadBuffer, WriteBuffer},
+	net::{
+		addr::SockAddr,
+		close_fd,
+		msg::{MsgIo, RecvMsg, SendMsg, cmsg_space},
+		sockopt::setsockopt,
+		stream::{READ_OP_ID, WRITE_OP_ID, stream_types},
+	},
+	rt::{UringRuntime, oneshot::run_oneshot, operation::poll_op_impl},
+};
+
+use super::{UCred, dup_fds};
+
+stream_types!(
+	UnixStream,
+	std::os::unix::net::UnixStream,
+	ReadHalf,
+	WriteHalf,
+	BorrowedReadHalf,
+	BorrowedWriteHalf,
+	ReuniteError
+);
+
+impl UnixStream {
+	/// Connect to the socket bound to `path`.
+	pub async fn connect(rt: &UringRuntime, path: impl AsRef<Path>) -> Result<Self> {
+		Self::connect_addr(rt, &SocketAddr::from_pathname(path)?).await
+	}
+
+	/// Connect to the socket bound to `addr`, which can also be in the abstract namespace (see
+	/// [`SocketAddrExt`](std::os::linux::net::SocketAddrExt)).
+	pub async fn connect_addr(rt: &UringRuntime, addr: &SocketAddr) -> Result<Self> {
+		let addr = SockAddr::from_unix(addr)?;
+
+		let entry =
+			opcode::Socket::new(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0).build();
+		// SAFETY: socket doesn't reference any memory
+		let fd = unsafe { run_oneshot(rt, entry, Some(close_fd)) }.await?;
+		// SAFETY: socket returned a new fd, which we now own
+		let std =
+			std::os::unix::net::UnixStream::from(unsafe { OwnedFd::from_raw_fd(fd.cast_signed()) });
+
+		let mut stream = Self::new(std, rt.data.clone(), rt.rt.clone()).await?;
+		stream.connect_raw(addr).await?;
+		Ok(stream)
+	}
+
+	/// Create a pair of connected streams.
+	pub async fn pair(rt: &UringRuntime) -> Result<(Self, Self)> {
+		let (a, b) = std::os::unix::net::UnixStream::pair()?;
+		Ok((Self::from_std(rt, a).await?, Self::from_std(rt, b).await?))
+	}
+
+	/// Register a connected stream with the runtime.
+	pub async fn from_std(rt: &UringRuntime, std: std::os::unix::net::UnixStream) -> Result<Self> {
+		Self::new(std, rt.data.clone(), rt.rt.clone()).await
+	}
+
+	fn with_std<R>(&self, f: impl FnOnce(&std::os::unix::net::UnixStream) -> R) -> R {
+		// SAFETY: the fd stays open for as long as we exist, and is never closed through this
+		let std =
+			ManuallyDrop::new(unsafe { std::os::unix::net::UnixStream::from_raw_fd(self.fd) });
+		f(&std)
+	}
+
+	pub fn local_addr(&self) -> io::Result<SocketAddr> {
+		self.with_std(std::os::unix::net::UnixStream::local_addr)
+	}
+
+	pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+		self.with_std(std::os::unix::net::UnixStream::peer_addr)
+	}
+
+	/// Credentials of the process on the other end, from when the connection was made.
+	pub fn peer_cred(&self) -> io::Result<UCred> {
+		super::peer_cred(self.fd)
+	}
+
+	/// Send the initialized part of an owned buffer along with `fds` (`SCM_RIGHTS`). The fds are
+	/// duplicated, so the caller can close its own copies right away.
+	///
+	/// The receiver gets the fds with the first byte of this data, so at least one byte has to be
+	/// sent. Buffered writes go out first.
+	pub async fn send_with_fds<B: IoBuf>(
+		&mut self,
+		buf: B,
+		fds: &[BorrowedFd<'_>],
+	) -> (io::Result<usize>, B) {
+		let fds = match dup_fds(fds) {
+			Ok(fds) => fds,
+			Err(err) => return (Err(err), buf),
+		};
+		SendMsg::new(self, buf, None).fds(fds).await
+	}
+
+	/// Read into an owned buffer, along with up to `max_fds` fds passed with the data. Further
+	/// fds are closed by the kernel.
+	///
+	/// The fds are returned close-on-exec; register them with the runtime through `from_std` of
+	/// the matching type. Fails with [`Error::ReadBuffered`](crate::Error::ReadBuffered) if
+	/// `AsyncRead` still buffers data, whose fds were already closed. If this future is dropped
+	/// before the read completes, any fds received in the meantime are closed.
+	pub async fn recv_with_fds<B: IoBufMut>(
+		&mut self,
+		buf: B,
+		max_fds: usize,
+	) -> (io::Result<(usize, Vec<OwnedFd>)>, B) {
+		let (ret, buf) = RecvMsg::new(self, buf).fds(max_fds).await;
+		(ret.map(|(cnt, mut msg)| (cnt, msg.take_fds())), buf)
+	}
+}
+
+impl UnixStream {
+	/// Have the kernel attach the sender's credentials to received data (`SO_PASSCRED`), see
+	/// [`recv_with_cred`](Self::recv_with_cred).
+	pub fn set_passcred(&self, passcred: bool) -> io::Result<()> {
+		setsockopt(
+			self.fd,
+			libc::SOL_SOCKET,
+			libc::SO_PASSCRED,
+			libc::c_int::from(passcred),
+		)
+	}
+
+	/// Send the initialized part of an owned buffer along with `cred` (`SCM_CREDENTIALS`), see
+	/// [`UnixDatagram::send_with_cred`](super::UnixDatagram::send_with_cred).
+	///
+	/// Buffered writes go out first.
+	pub async fn send_with_cred<B: IoBuf>(
+		&mut self,
+		buf: B,
+		cred: UCred,
+	) -> (io::Result<usize>, B) {
+		SendMsg::new(self, buf, None).cred(cred.to_raw()).await
+	}
+
+	/// Read into an owned buffer, along with the credentials of the sender (`SCM_CREDENTIALS`).
+	/// These are only attached with [`set_passcred`](Self::set_passcred) enabled, and a single
+	/// read never returns data sent with different credentials.
+	///
+	/// Fails with [`Error::ReadBuffered`](crate::Error::ReadBuffered) if `AsyncRead` still
+	/// buffers data, whose credentials are gone.
+	pub async fn recv_with_cred<B: IoBufMut>(
+		&mut self,
+		buf: B,
+	) -> (io::Result<(usize, Option<UCred>)>, B) {
+		let (ret, buf) = RecvMsg::new(self, buf)
+			.control(cmsg_space(size_of::<libc::ucred>()))
+			.await;
+		(
+			ret.map(|(cnt, msg)| (cnt, msg.cred().map(UCred::from_raw))),
+			buf,
+		)
+	}
+}
+
+impl MsgIo for UnixStream {
+	unsafe fn poll_send_msg(
+		&mut self,
+		cx: &mut Context<'_>,
+		msg: *const libc::msghdr,
+	) -> Poll<Result<u32>> {
+		let this = self;
+		poll_op_impl!(WRITE_OP_ID, this, cx, false, {
+			Some(Ok(val)) => |val| Poll::Ready(Ok(val)),
+			None => || Ok(opcode::SendMsg::new(this.file, msg).build())
+		})
+	}
+
+	unsafe fn poll_recv_msg(
+		&mut self,
+		cx: &mut Context<'_>,
+		msg: *mut libc::msghdr,
+		flags: u32,
+	) -> Poll<Result<u32>> {
+		let this = self;
+		poll_op_impl!(READ_OP_ID, this, cx, false, {
+			Some(Ok(val)) => |val| Poll::Ready(Ok(val)),
+			None => || Ok(opcode::RecvMsg::new(this.file, msg).flags(flags).build())
+		})
+	}
+
+	fn poll_prepare_send(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		WriteBuffer::poll_prepare(self, cx)
+	}
+
+	fn poll_prepare_recv(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		ready!(ReadBuffer::poll_prepare(self, cx))?;
+		// the fds sent along with buffered data are gone already
+		if self.read_buf.is_empty() {
+			Poll::Ready(Ok(()))
+		} else {
+			Poll::Ready(Err(Error::ReadBuffered.into()))
+		}
+	}
+}
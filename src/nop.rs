@@ -53,7 +53,7 @@ impl Stream for NopStream {
 		let this = &mut *self;
 		poll_op_impl!(Self::NOP_OP_ID, this, cx, false, {
 			Some(Ok(val)) => |val| Poll::Ready(Ok(val)),
-			None => || Ok(opcode::Nop::new())
+			None => || Ok(opcode::Nop::new().build())
 		})
 		.map(Some)
 	}
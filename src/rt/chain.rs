@@ -0,0 +1,535 @@
+use std::{
+	future::poll_fn,
+	io,
+	os::fd::{AsFd, AsRawFd, OwnedFd},
+	pin::Pin,
+	task::{Context, Poll},
+	time::Duration,
+};
+
+use futures::channel::oneshot;
+use io_uring::{
+	opcode,
+	squeue::{self, Flags},
+	types::{Fd, Timespec},
+};
+
+use crate::{
+	Error, Result,
+	buf::{IoBuf, IoBufMut},
+	compat::{BufferedRead, ReadBuffer, WriteBuffer},
+	net::{
+		stream::{CONNECT_OP_ID, READ_OP_ID, SHUTDOWN_OP_ID, WRITE_OP_ID},
+		tcp::TcpStream,
+	},
+};
+
+use super::{
+	UringDataHandle,
+	inner::{RuntimeWorkerChannel, WorkerMessage},
+	operation::{EventData, OperationCancelData, Operations, ProtectedOps},
+	resource::Resource,
+};
+
+/// Maximum number of operations in a chain, not counting link timeouts.
+pub const MAX_CHAIN_LEN: usize = 4;
+
+// chains run on the ops of their stream, with the read op last so shorter chains leave a read
+// buffered by `AsyncRead` alone
+const CHAIN_OP_IDS: [u32; MAX_CHAIN_LEN] = [SHUTDOWN_OP_ID, CONNECT_OP_ID, WRITE_OP_ID, READ_OP_ID];
+
+/// Buffer type of a chain without reads or writes, replaced by the type of the first one.
+pub enum NoBuf {}
+
+enum ChainOp<R, W> {
+	Nop,
+	Read(R),
+	Write(W),
+	// duplicated, so the fd can't be closed and reused while the fsync is in flight
+	Fsync(OwnedFd),
+}
+
+struct ChainEntry<R, W> {
+	op: ChainOp<R, W>,
+	// boxed so the kernel can read it after the entry is built
+	timeout: Option<Box<Timespec>>,
+	hard: bool,
+}
+
+/// Result of one operation in a chain.
+pub struct ChainResult<R, W> {
+	pub result: io::Result<usize>,
+	/// The buffer passed to `read` or `write`.
+	pub buf: Option<ChainBuf<R, W>>,
+}
+
+/// Buffer of a read or write in a chain, handed back with its result.
+pub enum ChainBuf<R, W> {
+	Read(R),
+	Write(W),
+}
+
+/// Builder for operations that are linked with `IOSQE_IO_LINK` and submitted together.
+///
+/// Each operation only starts once the previous one succeeded. If one fails, every following
+/// operation completes with `ECANCELED`. Only chains from [`TcpStream::chain`] can read and
+/// write, with buffers of type `R` and `W`.
+#[must_use = "chains do nothing unless submitted"]
+pub struct Chain<S = (), R = NoBuf, W = NoBuf> {
+	rt: UringDataHandle,
+	sender: RuntimeWorkerChannel,
+	// a stream is borrowed for the whole chain, so nothing else reads or writes it in between
+	stream: S,
+	entries: Vec<ChainEntry<R, W>>,
+	error: Option<Error>,
+}
+
+impl<S> Chain<S> {
+	pub(crate) fn new(rt: UringDataHandle, sender: RuntimeWorkerChannel, stream: S) -> Self {
+		Self {
+			rt,
+			sender,
+			stream,
+			entries: Vec::new(),
+			error: None,
+		}
+	}
+}
+
+impl<S, R, W> Chain<S, R, W> {
+	fn push(mut self, op: ChainOp<R, W>) -> Self {
+		if self.entries.len() < MAX_CHAIN_LEN {
+			self.entries.push(ChainEntry {
+				op,
+				timeout: None,
+				hard: false,
+			});
+		} else {
+			self.error = Some(Error::ChainTooLong);
+		}
+		self
+	}
+
+	/// Change the buffer types, for the first read or write.
+	fn map<R2, W2>(self, read: fn(R) -> R2, write: fn(W) -> W2) -> Chain<S, R2, W2> {
+		let entries = self.entries.into_iter().map(|entry| ChainEntry {
+			op: match entry.op {
+				ChainOp::Nop => ChainOp::Nop,
+				ChainOp::Read(buf) => ChainOp::Read(read(buf)),
+				ChainOp::Write(buf) => ChainOp::Write(write(buf)),
+				ChainOp::Fsync(fd) => ChainOp::Fsync(fd),
+			},
+			timeout: entry.timeout,
+			hard: entry.hard,
+		});
+
+		Chain {
+			rt: self.rt,
+			sender: self.sender,
+			stream: self.stream,
+			entries: entries.collect(),
+			error: self.error,
+		}
+	}
+
+	pub fn nop(self) -> Self {
+		self.push(ChainOp::Nop)
+	}
+
+	/// Sync the file behind `fd`, which the chain duplicates so it stays open until the fsync
+	/// completes.
+	pub fn fsync(mut self, fd: &impl AsFd) -> Self {
+		match fd.as_fd().try_clone_to_owned() {
+			Ok(fd) => self.push(ChainOp::Fsync(fd)),
+			Err(err) => {
+				self.error = Some(err.into());
+				self
+			}
+		}
+	}
+
+	/// Cancel the previous operation if it doesn't complete within `timeout`.
+	///
+	/// The operation then fails with `ErrorKind::TimedOut`.
+	pub fn link_timeout(mut self, timeout: Duration) -> Self {
+		if let Some(entry) = self.entries.last_mut() {
+			entry.timeout = Some(Box::new(timeout.into()));
+		}
+		self
+	}
+
+	/// Link the previous operation with `IOSQE_IO_HARDLINK`, so the next operation runs even if
+	/// it fails.
+	pub fn hardlink(mut self) -> Self {
+		if let Some(entry) = self.entries.last_mut() {
+			entry.hard = true;
+		}
+		self
+	}
+}
+
+impl<'a, W> Chain<&'a mut TcpStream, NoBuf, W> {
+	/// Receive from the stream into `buf`, filling it from the start up to its total capacity.
+	///
+	/// The first read sets the buffer type of every read in the chain.
+	pub fn read<R: IoBufMut>(self, buf: R) -> Chain<&'a mut TcpStream, R, W> {
+		self.map(|buf| match buf {}, |buf| buf)
+			.push(ChainOp::Read(buf))
+	}
+}
+
+impl<R: IoBufMut, W> Chain<&mut TcpStream, R, W> {
+	/// Receive from the stream into `buf`, filling it from the start up to its total capacity.
+	pub fn read(self, buf: R) -> Self {
+		self.push(ChainOp::Read(buf))
+	}
+}
+
+impl<'a, R> Chain<&'a mut TcpStream, R, NoBuf> {
+	/// Send the initialized part of `buf` on the stream.
+	///
+	/// The first write sets the buffer type of every write in the chain.
+	pub fn write<W: IoBuf>(self, buf: W) -> Chain<&'a mut TcpStream, R, W> {
+		self.map(|buf| buf, |buf| match buf {})
+			.push(ChainOp::Write(buf))
+	}
+}
+
+impl<R, W: IoBuf> Chain<&mut TcpStream, R, W> {
+	/// Send the initialized part of `buf` on the stream.
+	pub fn write(self, buf: W) -> Self {
+		self.push(ChainOp::Write(buf))
+	}
+}
+
+impl Chain {
+	/// Submit the chain and wait for every operation in it to complete.
+	pub async fn submit(self) -> Result<Vec<ChainResult<NoBuf, NoBuf>>> {
+		if let Some(err) = self.error {
+			return Err(err);
+		}
+
+		let (tx, rx) = oneshot::channel();
+		self.sender.send(WorkerMessage::RegisterResource {
+			ops: Operations::new_from_size(),
+			fd: None,
+			complete: tx,
+		})?;
+		let resource = rx.await.map_err(|_| Error::NoRuntime)??;
+
+		ChainFuture::new(
+			self.rt,
+			ChainResource::Owned(resource, self.sender),
+			self.entries,
+		)
+		.await
+	}
+}
+
+#[expect(private_bounds)]
+impl<R: ChainOpBuf<dyn IoBufMut>, W: ChainOpBuf<dyn IoBuf>> Chain<&mut TcpStream, R, W> {
+	/// Submit the chain and wait for every operation in it to complete.
+	///
+	/// Writes buffered by the stream's `AsyncWrite` go out first. A chain that reads fails with
+	/// [`Error::ReadBuffered`] if the stream's `AsyncRead` still buffers data. Ops that leaked
+	/// futures left in flight on the stream complete before the chain is submitted.
+	pub async fn submit(self) -> Result<Vec<ChainResult<R, W>>> {
+		if let Some(err) = self.error {
+			return Err(err);
+		}
+
+		let stream = self.stream;
+		Self::prepare(stream, &self.entries).await?;
+
+		ChainFuture::new(
+			self.rt,
+			ChainResource::Stream(stream.resource_mut()),
+			self.entries,
+		)
+		.await
+	}
+
+	/// Get the stream's ops ready for the chain to take over.
+	async fn prepare(stream: &mut TcpStream, entries: &[ChainEntry<R, W>]) -> Result<()> {
+		let ids = &CHAIN_OP_IDS[..entries.len()];
+
+		poll_fn(|cx| WriteBuffer::poll_prepare(stream, cx)).await?;
+		if entries
+			.iter()
+			.any(|entry| matches!(entry.op, ChainOp::Read(_)))
+		{
+			poll_fn(|cx| ReadBuffer::poll_prepare(stream, cx)).await?;
+			if !stream.read_buffer().is_empty() {
+				return Err(Error::ReadBuffered);
+			}
+		} else if ids.contains(&READ_OP_ID) {
+			// the data of a read that's in flight stays buffered for later reads
+			let mut interrupted = false;
+			poll_fn(|cx| ReadBuffer::poll_interrupt(stream, cx, &mut interrupted)).await?;
+		}
+
+		for &id in ids {
+			stream.reclaim_op(id);
+			let resource = stream.resource_mut();
+			// wait for a cancelled op to complete, so its completion doesn't go to the chain
+			poll_fn(|cx| resource.ops.poll_submit_id(id, cx).map(|_| ())).await;
+		}
+
+		Ok(())
+	}
+}
+
+/// Gives the chain access to the buffers of its ops, which [`NoBuf`] never has.
+trait ChainOpBuf<B: ?Sized>: Unpin + Send + 'static {
+	fn get(&mut self) -> &mut B;
+}
+
+impl<T: IoBufMut> ChainOpBuf<dyn IoBufMut> for T {
+	fn get(&mut self) -> &mut (dyn IoBufMut + 'static) {
+		self
+	}
+}
+
+impl<T: IoBuf> ChainOpBuf<dyn IoBuf> for T {
+	fn get(&mut self) -> &mut (dyn IoBuf + 'static) {
+		self
+	}
+}
+
+impl<B: ?Sized> ChainOpBuf<B> for NoBuf {
+	fn get(&mut self) -> &mut B {
+		match *self {}
+	}
+}
+
+enum ChainResource<'a> {
+	/// Ops of the stream the chain borrows, which stays registered until they complete.
+	Stream(&'a mut Resource),
+	/// Registered for a chain without a stream, and closed once its ops complete.
+	Owned(Resource, RuntimeWorkerChannel),
+}
+
+impl ChainResource<'_> {
+	fn get(&mut self) -> &mut Resource {
+		match self {
+			Self::Stream(resource) => resource,
+			Self::Owned(resource, _) => resource,
+		}
+	}
+}
+
+struct ChainFuture<'a, R: ChainOpBuf<dyn IoBufMut>, W: ChainOpBuf<dyn IoBuf>> {
+	rt: UringDataHandle,
+	resource: ChainResource<'a>,
+
+	entries: Vec<ChainEntry<R, W>>,
+	results: Vec<Option<io::Result<usize>>>,
+	submitted: bool,
+}
+
+impl<'a, R: ChainOpBuf<dyn IoBufMut>, W: ChainOpBuf<dyn IoBuf>> ChainFuture<'a, R, W> {
+	fn new(
+		rt: UringDataHandle,
+		resource: ChainResource<'a>,
+		entries: Vec<ChainEntry<R, W>>,
+	) -> Self {
+		Self {
+			rt,
+			resource,
+			entries,
+			results: Vec::new(),
+			submitted: false,
+		}
+	}
+
+	fn build(&mut self) -> Result<Vec<squeue::Entry>> {
+		let len = self.entries.len();
+		let mut built = Vec::with_capacity(len * 2);
+		let resource = self.resource.get();
+
+		for (i, (entry, id)) in self.entries.iter_mut().zip(CHAIN_OP_IDS).enumerate() {
+			let sqe = match &mut entry.op {
+				ChainOp::Nop => opcode::Nop::new().build(),
+				ChainOp::Read(buf) => {
+					let fd = resource.file.expect("chain has a stream");
+					let buf = buf.get();
+					let (ptr, index) = (buf.stable_mut_ptr(), buf.buf_index());
+					let len = buf
+						.bytes_total()
+						.try_into()
+						.map_err(|_| Error::BufferTooLarge)?;
+					match index {
+						Some(index) => opcode::ReadFixed::new(fd, ptr, len, index).build(),
+						None => opcode::Recv::new(fd, ptr, len).build(),
+					}
+				}
+				ChainOp::Write(buf) => {
+					let fd = resource.file.expect("chain has a stream");
+					let buf = buf.get();
+					let (ptr, index) = (buf.stable_ptr(), buf.buf_index());
+					let len = buf
+						.bytes_init()
+						.try_into()
+						.map_err(|_| Error::BufferTooLarge)?;
+					match index {
+						Some(index) => opcode::WriteFixed::new(fd, ptr, len, index).build(),
+						None => opcode::Send::new(fd, ptr, len).build(),
+					}
+				}
+				ChainOp::Fsync(fd) => opcode::Fsync::new(Fd(fd.as_raw_fd())).build(),
+			};
+
+			let sqe = sqe.user_data(
+				EventData {
+					resource: resource.id,
+					id,
+				}
+				.into(),
+			);
+
+			let last = i == len - 1;
+			let link = if entry.hard {
+				Flags::IO_HARDLINK
+			} else {
+				Flags::IO_LINK
+			};
+
+			if let Some(timeout) = &entry.timeout {
+				// the timeout is linked to the op, and continues the chain itself
+				built.push(sqe.flags(link));
+				let timeout = opcode::LinkTimeout::new(&raw const **timeout)
+					.build()
+					.user_data(EventData::UNTRACKED);
+				built.push(if last { timeout } else { timeout.flags(link) });
+			} else {
+				built.push(if last { sqe } else { sqe.flags(link) });
+			}
+		}
+
+		Ok(built)
+	}
+
+	fn start(&mut self, cx: &mut Context<'_>) -> Result<()> {
+		let handle = self.rt.clone();
+		let Some(rt) = handle.load() else {
+			return Err(Error::NoRuntime);
+		};
+
+		let built = self.build()?;
+		let ids = &CHAIN_OP_IDS[..self.entries.len()];
+		let resource = self.resource.get();
+
+		for &id in ids {
+			resource.ops.prepare_submit(id, cx);
+		}
+
+		// SAFETY: buffers and timeouts are kept alive until the ops complete, or moved into the
+		// runtime if we are dropped
+		if let Err(err) = unsafe { rt.submit_all(&built) } {
+			for &id in ids {
+				resource.ops.abort_submit(id);
+			}
+			return Err(err);
+		}
+
+		self.results = self.entries.iter().map(|_| None).collect();
+		self.submitted = true;
+
+		Ok(())
+	}
+
+	fn finish(&mut self) -> Vec<ChainResult<R, W>> {
+		let mut failed = false;
+		let mut out = Vec::with_capacity(self.entries.len());
+
+		for (entry, result) in self.entries.drain(..).zip(self.results.drain(..)) {
+			let mut result = result.expect("op was not finished");
+
+			// ECANCELED without a previous failure means the link timeout fired
+			if entry.timeout.is_some()
+				&& !failed && let Err(err) = &result
+				&& err.raw_os_error() == Some(libc::ECANCELED)
+			{
+				result = Err(io::ErrorKind::TimedOut.into());
+			}
+			failed |= result.is_err() && !entry.hard;
+
+			let buf = match entry.op {
+				ChainOp::Read(buf) => Some(ChainBuf::Read(buf)),
+				ChainOp::Write(buf) => Some(ChainBuf::Write(buf)),
+				ChainOp::Nop | ChainOp::Fsync(_) => None,
+			};
+			out.push(ChainResult { result, buf });
+		}
+
+		out
+	}
+}
+
+impl<R: ChainOpBuf<dyn IoBufMut>, W: ChainOpBuf<dyn IoBuf>> Future for ChainFuture<'_, R, W> {
+	type Output = Result<Vec<ChainResult<R, W>>>;
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		let this = &mut *self;
+
+		if !this.submitted
+			&& let Err(err) = this.start(cx)
+		{
+			return Poll::Ready(Err(err));
+		}
+
+		let mut pending = false;
+		let resource = this.resource.get();
+		for (i, (slot, id)) in this.results.iter_mut().zip(CHAIN_OP_IDS).enumerate() {
+			if slot.is_some() {
+				continue;
+			}
+
+			match resource.ops.poll_submit_id(id, cx) {
+				Poll::Ready(Some(ret)) => {
+					let entry = &mut this.entries[i];
+					let ret = ret.map(|x| x as usize);
+					if let (ChainOp::Read(buf), Ok(cnt)) = (&mut entry.op, &ret) {
+						// SAFETY: kernel just initialized these bytes in the read op
+						unsafe { buf.get().set_init(*cnt) };
+					}
+					*slot = Some(ret.map_err(Into::into));
+				}
+				Poll::Ready(None) => unreachable!("chain op was not submitted"),
+				Poll::Pending => pending = true,
+			}
+		}
+
+		if pending {
+			Poll::Pending
+		} else {
+			Poll::Ready(Ok(this.finish()))
+		}
+	}
+}
+
+impl<R: ChainOpBuf<dyn IoBufMut>, W: ChainOpBuf<dyn IoBuf>> Drop for ChainFuture<'_, R, W> {
+	fn drop(&mut self) {
+		let resource = self.resource.get();
+		for ((entry, result), id) in self.entries.iter_mut().zip(&self.results).zip(CHAIN_OP_IDS) {
+			if result.is_none() {
+				resource.cancel(
+					self.rt.load(),
+					id,
+					OperationCancelData {
+						wake: true,
+						buf: Box::new((
+							entry.timeout.take(),
+							std::mem::replace(&mut entry.op, ChainOp::Nop),
+						)),
+						release: None,
+					},
+				);
+			}
+		}
+
+		if let ChainResource::Owned(resource, sender) = &self.resource {
+			let _ = sender.send(WorkerMessage::CloseResource(resource.dup()));
+		}
+	}
+}
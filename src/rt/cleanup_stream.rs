@@ -5,17 +5,22 @@ use std::{
 
 use futures::Stream;
 
-use crate::rt::{inner::WorkerMessage, operation::OperationPollState, resource::Resource};
+use crate::rt::{
+	inner::{FinishAction, WorkerMessage},
+	resource::Resource,
+};
 
 struct ClosingResource {
 	resource: Resource<4>,
 	polled: [bool; 4],
+	action: FinishAction,
 }
 impl ClosingResource {
-	fn new(resource: Resource) -> Self {
+	fn new(resource: Resource, action: FinishAction) -> Self {
 		Self {
 			resource,
 			polled: [false; 4],
+			action,
 		}
 	}
 
@@ -36,13 +41,7 @@ impl ClosingResource {
 
 		self.polled = [false; 4];
 
-		Poll::Ready(
-			!self
-				.resource
-				.ops
-				.poll_states()
-				.any(|x| matches!(x, OperationPollState::Submitting)),
-		)
+		Poll::Ready(!self.resource.ops.in_flight())
 	}
 }
 
@@ -51,11 +50,13 @@ pub(crate) struct CleanupStream {
 }
 impl CleanupStream {
 	pub fn new() -> Self {
-		Self { resources: Vec::new() }
+		Self {
+			resources: Vec::new(),
+		}
 	}
 
-	pub fn push(&mut self, resource: Resource) {
-		self.resources.push(ClosingResource::new(resource));
+	pub fn push(&mut self, resource: Resource, action: FinishAction) {
+		self.resources.push(ClosingResource::new(resource, action));
 	}
 }
 impl Stream for CleanupStream {
@@ -70,10 +71,13 @@ impl Stream for CleanupStream {
 			}
 		}
 		if let Some(i) = remove {
-			Poll::Ready(Some(Ok(WorkerMessage::FinishResource(self.resources.remove(i).resource))))
+			let closed = self.resources.remove(i);
+			Poll::Ready(Some(Ok(WorkerMessage::FinishResource(
+				closed.resource,
+				closed.action,
+			))))
 		} else {
 			Poll::Pending
 		}
 	}
 }
-
@@ -1,20 +1,23 @@
 use std::{
-	os::fd::{IntoRawFd, OwnedFd},
+	os::fd::{AsRawFd, OwnedFd},
 	sync::{Arc, atomic::AtomicBool},
 };
 
 use futures::{StreamExt, TryStreamExt};
-use io_uring::cqueue;
+use io_uring::{cqueue, types::Fixed};
 
-use crate::{rt::{cleanup_stream::CleanupStream, operation::OperationPollState}, Result};
+use crate::{Result, rt::cleanup_stream::CleanupStream};
 
 use super::{
-	UringDataHandle,
+	UringData, UringDataHandle,
 	channel::{ChannelRecv, ChannelSend},
 	completion::CqueueStream,
 	deps::AsyncFd,
 	operation::{EventData, Operations},
-	resource::{RegisterResourceSender, Resource, WorkerResource, WorkerResourceSlab},
+	resource::{
+		DeregisterResourceSender, FixedFiles, RegisterResourceSender, Resource, WorkerResource,
+		WorkerResourceSlab,
+	},
 	select::{PollNext, select_with_strategy},
 };
 
@@ -34,11 +37,34 @@ pub(crate) enum WorkerMessage {
 		ops: Operations,
 		complete: RegisterResourceSender,
 	},
+	/// Like `RegisterResource`, but with an empty slot in the file table instead of an fd.
+	RegisterDirect {
+		ops: Operations,
+		complete: RegisterResourceSender,
+	},
 	CloseResource(Resource),
-	FinishResource(Resource),
+	/// Like `CloseResource`, but hands the fd back instead of closing it.
+	DeregisterResource {
+		resource: Resource,
+		complete: DeregisterResourceSender,
+	},
+	/// Shut down one direction of a socket once the ops of this handle complete, leaving it
+	/// registered for the other handles.
+	ShutdownResource {
+		resource: Resource,
+		how: i32,
+	},
+	FinishResource(Resource, FinishAction),
 	Stop,
 }
 
+/// What to do with a resource once none of its ops are in flight.
+pub(crate) enum FinishAction {
+	Close,
+	Deregister(DeregisterResourceSender),
+	Shutdown(i32),
+}
+
 pub(crate) struct UringRuntimeWorker {
 	rt: ChannelRecv<WorkerMessage>,
 }
@@ -69,17 +95,25 @@ impl UringRuntimeWorker {
 				}
 			},
 		);
-		let mut combined = select_with_strategy(uring, CleanupStream::new(), |x: &mut PollNext| x.toggle());
+		let mut combined =
+			select_with_strategy(uring, CleanupStream::new(), |x: &mut PollNext| x.toggle());
 
 		let mut resources = WorkerResourceSlab::new();
+		let mut files = FixedFiles::new(data.max_files);
 
 		while let Some(evt) = combined.next().await.transpose()? {
 			match evt {
 				WorkerMessage::Uring { info, event } => {
+					if event.user_data() == EventData::UNTRACKED {
+						continue;
+					}
+
 					if let Some(resource) = resources.get(info.resource) {
 						if let Some(op) = resource.ops.get(info.id) {
 							// this drops any data that was needed for the op if it was cancelled
-							op.wake(event.result());
+							if op.wake(event.result(), event.flags()) {
+								data.recycle_buffer(event.flags());
+							}
 						} else {
 							panic!("dropped message {info:?}");
 						}
@@ -88,40 +122,34 @@ impl UringRuntimeWorker {
 					}
 				}
 				WorkerMessage::RegisterResource { ops, fd, complete } => {
-					let is_closing = Arc::new(AtomicBool::new(false));
-					let worker = WorkerResource {
-						ops: ops.clone(),
-						fd,
-						closing: is_closing.clone(),
-					};
-
-					match resources.insert(worker) {
-						Ok(id) => {
-							let resource = Resource::new(id, ops, is_closing);
-							let _ = complete.send(Ok(resource));
-						}
-						Err(err) => {
-							let _ = complete.send(Err(err));
-						}
-					}
+					let file = fd
+						.as_ref()
+						.map(|fd| files.insert(data, fd.as_raw_fd()))
+						.transpose();
+					register_resource(data, &mut resources, &mut files, ops, fd, file, complete);
+				}
+				WorkerMessage::RegisterDirect { ops, complete } => {
+					let file = files.reserve().map(Some);
+					register_resource(data, &mut resources, &mut files, ops, None, file, complete);
 				}
 				WorkerMessage::CloseResource(resource) => {
-					combined.get_mut().1.push(resource);
+					combined.get_mut().1.push(resource, FinishAction::Close);
 				}
-				WorkerMessage::FinishResource(mut resource) => {
-					debug_assert!(
-						!resource
-							.ops
-							.poll_states()
-							.any(|x| matches!(x, OperationPollState::Submitting))
-					);
-
-					if let Some(mut val) = resources.remove(resource.id)
-						&& val.closing() && let Some(fd) = val.fd.take()
-					{
-						// uring already closed the fd
-						let _ = fd.into_raw_fd();
-					}
+				WorkerMessage::DeregisterResource { resource, complete } => {
+					combined
+						.get_mut()
+						.1
+						.push(resource, FinishAction::Deregister(complete));
+				}
+				WorkerMessage::ShutdownResource { resource, how } => {
+					combined
+						.get_mut()
+						.1
+						.push(resource, FinishAction::Shutdown(how));
+				}
+				WorkerMessage::FinishResource(resource, action) => {
+					debug_assert!(!resource.ops.in_flight());
+					finish_resource(data, &mut resources, &mut files, &resource, action);
 				}
 				WorkerMessage::Stop => break,
 			}
@@ -132,3 +160,81 @@ impl UringRuntimeWorker {
 		Ok(())
 	}
 }
+
+fn register_resource(
+	data: &UringData,
+	resources: &mut WorkerResourceSlab,
+	files: &mut FixedFiles,
+	ops: Operations,
+	fd: Option<OwnedFd>,
+	file: Result<Option<u32>>,
+	complete: RegisterResourceSender,
+) {
+	let file = match file {
+		Ok(file) => file.map(Fixed),
+		Err(err) => {
+			let _ = complete.send(Err(err));
+			return;
+		}
+	};
+
+	let worker = WorkerResource {
+		ops: ops.clone(),
+		fd,
+		file,
+	};
+
+	match resources.insert(worker) {
+		Ok(id) => {
+			let resource = Resource::new(id, ops, file, Arc::new(AtomicBool::new(false)));
+			// nobody is waiting for it anymore, so nothing will ever close it
+			if complete.send(Ok(resource)).is_err() {
+				resources.remove(id);
+				if let Some(Fixed(slot)) = file {
+					files.remove(data, slot);
+				}
+			}
+		}
+		Err(err) => {
+			if let Some(Fixed(slot)) = file {
+				files.remove(data, slot);
+			}
+			let _ = complete.send(Err(err));
+		}
+	}
+}
+
+fn finish_resource(
+	data: &UringData,
+	resources: &mut WorkerResourceSlab,
+	files: &mut FixedFiles,
+	resource: &Resource,
+	action: FinishAction,
+) {
+	// the socket may have been closed in the meantime, which shuts it down anyway, and its id
+	// given to another resource
+	if !resources
+		.get(resource.id)
+		.is_some_and(|val| val.ops.same(&resource.ops))
+	{
+		return;
+	}
+
+	if let FinishAction::Shutdown(how) = action {
+		if let Some(fd) = resources.get(resource.id).and_then(|val| val.fd.as_ref()) {
+			// SAFETY: the fd is open for as long as the resource is registered.
+			// errors only matter to the handle that's gone now
+			unsafe { libc::shutdown(fd.as_raw_fd(), how) };
+		}
+	} else if let Some(mut val) = resources.remove(resource.id) {
+		if let Some(Fixed(slot)) = val.file {
+			files.remove(data, slot);
+		}
+		// the fd is closed if nobody is waiting for it anymore
+		if let FinishAction::Deregister(complete) = action
+			&& let Some(fd) = val.fd.take()
+		{
+			let _ = complete.send(fd);
+		}
+	}
+}
@@ -7,20 +7,29 @@ use std::{
 };
 
 use inner::{RuntimeWorkerChannel, UringRuntimeWorker, WorkerMessage};
-use io_uring::{IoUring, cqueue, squeue};
+use io_uring::{IoUring, Probe, cqueue, squeue};
 
-use crate::{Result, net::tcp::TcpStream, nop::NopStream};
+use crate::{
+	Error, Result,
+	buf::{BUF_RING_GROUP, BufRing, FixedBuf, FixedBufPool},
+	net::tcp::TcpStream,
+	nop::NopStream,
+};
 
+mod chain;
 mod channel;
+mod cleanup_stream;
 mod completion;
 mod deps;
 mod select;
-mod cleanup_stream;
 
 pub(crate) mod inner;
+pub(crate) mod oneshot;
 pub(crate) mod operation;
+pub(crate) mod owned;
 pub(crate) mod resource;
 
+pub use chain::{Chain, ChainBuf, ChainResult, MAX_CHAIN_LEN, NoBuf};
 pub use deps::AsyncFd;
 
 pub(crate) type Uring = IoUring<squeue::Entry, cqueue::Entry>;
@@ -50,25 +59,70 @@ pub(crate) struct UringData {
 
 	sq_lock: Mutex<()>,
 	uring: Uring,
+	// opcodes the kernel supports
+	probe: Probe,
+	max_files: u32,
+	// dropped after the ring, so registered memory outlives it
+	buffers: Option<Arc<FixedBufPool>>,
+	buf_ring: Option<Arc<BufRing>>,
 }
 impl UringData {
-	pub fn new(uring: Uring) -> Self {
+	pub fn new(
+		uring: Uring,
+		probe: Probe,
+		max_files: u32,
+		buffers: Option<Arc<FixedBufPool>>,
+		buf_ring: Option<Arc<BufRing>>,
+	) -> Self {
 		Self {
 			alive: AtomicBool::new(true),
 			uring,
+			probe,
+			max_files,
+			buffers,
+			buf_ring,
 
 			sq_lock: Mutex::new(()),
 		}
 	}
 
+	pub fn supports(&self, opcode: u8) -> bool {
+		self.probe.is_supported(opcode)
+	}
+
+	pub fn buf_ring(&self) -> Result<&Arc<BufRing>> {
+		self.buf_ring.as_ref().ok_or(Error::NoBufferRing)
+	}
+
+	/// Give the buffer the kernel picked for a completion back to the ring, if it picked one.
+	pub fn recycle_buffer(&self, flags: u32) {
+		if let Some(bid) = cqueue::buffer_select(flags)
+			&& let Some(ring) = &self.buf_ring
+		{
+			ring.recycle(bid);
+		}
+	}
+
 	/// SAFETY: make sure entry will stay alive
 	pub unsafe fn submit(&self, entry: &squeue::Entry) -> Result<()> {
+		// SAFETY: enforced by the caller
+		unsafe { self.submit_all(std::slice::from_ref(entry)) }
+	}
+
+	/// Submit entries contiguously, so that links between them aren't broken up.
+	///
+	/// SAFETY: make sure entries will stay alive
+	pub unsafe fn submit_all(&self, entries: &[squeue::Entry]) -> Result<()> {
 		let lock = self.sq_lock.lock().unwrap();
 		// SAFETY: sq is protected by the lock
 		let mut sq = unsafe { self.uring.submission_shared() };
 
+		if entries.len() > sq.capacity() {
+			return Err(Error::TooManyEntries);
+		}
+
 		// SAFETY: enforced by the caller
-		while unsafe { sq.push(entry) }.is_err() {
+		while unsafe { sq.push_multiple(entries) }.is_err() {
 			match self.uring.submit() {
 				Ok(_) => {}
 				Err(x) => {
@@ -91,6 +145,9 @@ impl UringData {
 }
 
 pub struct UringRuntimeBuilder<Fd: AsyncFd> {
+	max_files: Option<u32>,
+	fixed_buffers: Option<(u16, usize)>,
+	provided_buffers: Option<(u16, usize)>,
 	phantom: PhantomData<Fd>,
 }
 
@@ -103,16 +160,129 @@ impl<Fd: AsyncFd> Default for UringRuntimeBuilder<Fd> {
 impl<Fd: AsyncFd> UringRuntimeBuilder<Fd> {
 	pub fn new() -> Self {
 		Self {
+			max_files: None,
+			fixed_buffers: None,
+			provided_buffers: None,
 			phantom: PhantomData,
 		}
 	}
 
+	/// Size of the registered file table, which limits how many resources can be registered at
+	/// once. Defaults to the `RLIMIT_NOFILE` soft limit.
+	///
+	/// The kernel allocates the whole table up front, at about 8 bytes per slot. If it can't, the
+	/// table is shrunk until it fits.
+	#[must_use]
+	pub fn max_files(mut self, max_files: u32) -> Self {
+		self.max_files = Some(max_files);
+		self
+	}
+
+	/// Register a pool of `count` buffers of `size` bytes each with the ring.
+	///
+	/// Buffers are checked out with [`UringRuntime::fixed_buf`].
+	#[must_use]
+	pub fn fixed_buffers(mut self, count: u16, size: usize) -> Self {
+		self.fixed_buffers = Some((count, size));
+		self
+	}
+
+	/// Register a ring of `count` buffers of `size` bytes each, which the kernel picks from when
+	/// data arrives. `count` must be a power of two.
+	///
+	/// Used by `recv_provided`, so idle connections don't hold on to a buffer.
+	#[must_use]
+	pub fn provided_buffers(mut self, count: u16, size: usize) -> Self {
+		self.provided_buffers = Some((count, size));
+		self
+	}
+
+	fn default_max_files() -> u32 {
+		let mut limit = libc::rlimit {
+			rlim_cur: 0,
+			rlim_max: 0,
+		};
+		// SAFETY: limit is a valid rlimit
+		if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &raw mut limit) } == 0 {
+			// IORING_MAX_FIXED_FILES
+			u32::try_from(limit.rlim_cur.min(1 << 20)).unwrap_or(1024)
+		} else {
+			1024
+		}
+	}
+
+	/// Register an empty file table of up to `max_files` slots, returning how many it got.
+	fn register_files(uring: &Uring, max_files: u32) -> Result<u32> {
+		let mut size = max_files;
+		loop {
+			let ret = uring
+				.submitter()
+				.register_files_sparse(size)
+				.or_else(|err| match err.raw_os_error() {
+					// kernels before 5.19 only take tables of empty fds
+					Some(libc::EINVAL) => {
+						uring.submitter().register_files(&vec![-1; size as usize])
+					}
+					_ => Err(err),
+				});
+
+			match ret {
+				Ok(()) => return Ok(size),
+				// too big for the kernel, the fd limit or memory
+				Err(err)
+					if size > 1
+						&& matches!(
+							err.raw_os_error(),
+							Some(libc::EINVAL | libc::EMFILE | libc::ENOMEM)
+						) =>
+				{
+					size /= 2;
+				}
+				Err(err) => return Err(err.into()),
+			}
+		}
+	}
+
 	pub fn build(self) -> Result<(UringRuntime, impl Future<Output = Result<()>> + Send)> {
 		let uring = IoUring::builder()
 			.setup_submit_all()
 			.setup_sqpoll(1_000)
 			.build(1024)?;
-		let data = UringDataHandle::new(UringData::new(uring));
+
+		let mut probe = Probe::new();
+		uring.submitter().register_probe(&mut probe)?;
+
+		let max_files = self.max_files.unwrap_or_else(Self::default_max_files);
+		let max_files = Self::register_files(&uring, max_files)?;
+
+		let buffers = match self.fixed_buffers {
+			Some((count, size)) => {
+				let pool = Arc::new(FixedBufPool::new(count, size)?);
+				// SAFETY: the pool is kept alive by `UringData` until the ring is dropped
+				unsafe { uring.submitter().register_buffers(&pool.iovecs())? };
+				Some(pool)
+			}
+			None => None,
+		};
+
+		let buf_ring = match self.provided_buffers {
+			Some((count, size)) => {
+				let ring = Arc::new(BufRing::new(count, size)?);
+				// SAFETY: the ring is kept alive by `UringData` until the ring is dropped
+				unsafe {
+					uring.submitter().register_buf_ring_with_flags(
+						ring.addr(),
+						ring.entries(),
+						BUF_RING_GROUP,
+						0,
+					)?;
+				};
+				Some(ring)
+			}
+			None => None,
+		};
+
+		let data = UringDataHandle::new(UringData::new(uring, probe, max_files, buffers, buf_ring));
 
 		let (rt, channel) = UringRuntimeWorker::new();
 
@@ -127,8 +297,8 @@ impl<Fd: AsyncFd> UringRuntimeBuilder<Fd> {
 }
 
 pub struct UringRuntime {
-	data: UringDataHandle,
-	rt: RuntimeWorkerChannel,
+	pub(crate) data: UringDataHandle,
+	pub(crate) rt: RuntimeWorkerChannel,
 }
 
 impl UringRuntime {
@@ -144,6 +314,20 @@ impl UringRuntime {
 		NopStream::new(self.data.clone(), self.rt.clone()).await
 	}
 
+	/// Check out a buffer from the registered buffer pool.
+	///
+	/// Returns `None` if every buffer is in use or no pool was set up with
+	/// [`UringRuntimeBuilder::fixed_buffers`].
+	pub fn fixed_buf(&self) -> Option<FixedBuf> {
+		self.data.0.buffers.as_ref()?.checkout()
+	}
+
+	/// Start building a chain of linked operations that don't use a stream, see
+	/// [`TcpStream::chain`](crate::net::tcp::TcpStream::chain) for ones that do.
+	pub fn chain(&self) -> Chain {
+		Chain::new(self.data.clone(), self.rt.clone(), ())
+	}
+
 	pub fn stop(&self) -> Result<()> {
 		self.rt.send(WorkerMessage::Stop)
 	}
@@ -0,0 +1,96 @@
+use std::{
+	pin::Pin,
+	task::{Context, Poll, ready},
+};
+
+use futures::channel::oneshot;
+use io_uring::squeue;
+
+use crate::{Error, Result};
+
+use super::{
+	UringDataHandle, UringRuntime,
+	inner::{RuntimeWorkerChannel, WorkerMessage},
+	operation::{OperationCancelData, Operations, poll_op_impl},
+	resource::Resource,
+};
+
+const ONESHOT_OP_ID: u32 = 0;
+
+/// Run a single op that doesn't belong to a registered fd, like creating a socket, on a
+/// temporary resource.
+///
+/// If the future is dropped before the op completes, `release` is called with its result.
+///
+/// SAFETY: the entry must not reference any memory
+pub(crate) async unsafe fn run_oneshot(
+	rt: &UringRuntime,
+	entry: squeue::Entry,
+	release: Option<fn(i32)>,
+) -> Result<u32> {
+	let (tx, rx) = oneshot::channel();
+
+	rt.rt.send(WorkerMessage::RegisterResource {
+		ops: Operations::new_from_size(),
+		fd: None,
+		complete: tx,
+	})?;
+
+	let resource = rx.await.map_err(|_| Error::NoRuntime)??;
+
+	OneshotFuture {
+		rt: rt.data.clone(),
+		resource,
+		sender: rt.rt.clone(),
+		entry: Some(entry),
+		release,
+		done: false,
+	}
+	.await
+}
+
+struct OneshotFuture {
+	rt: UringDataHandle,
+	resource: Resource,
+	sender: RuntimeWorkerChannel,
+
+	entry: Option<squeue::Entry>,
+	release: Option<fn(i32)>,
+	done: bool,
+}
+
+impl Future for OneshotFuture {
+	type Output = Result<u32>;
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		let this = &mut *self;
+		let ret = ready!(poll_op_impl!(ONESHOT_OP_ID, this, cx, false, {
+			Some(Ok(val)) => |val| Poll::Ready(Ok(val)),
+			None => || Ok(this.entry.take().expect("oneshot op was already submitted"))
+		}));
+		this.done = true;
+
+		Poll::Ready(ret)
+	}
+}
+
+impl Drop for OneshotFuture {
+	fn drop(&mut self) {
+		if !self.done {
+			let rt = self.rt.clone();
+			self.resource.cancel(
+				rt.load(),
+				ONESHOT_OP_ID,
+				OperationCancelData {
+					wake: true,
+					buf: Box::new(()),
+					release: self.release,
+				},
+			);
+		}
+
+		let _ = self
+			.sender
+			.send(WorkerMessage::CloseResource(self.resource.dup()));
+	}
+}
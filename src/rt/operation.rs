@@ -1,19 +1,25 @@
 use std::{
+	collections::VecDeque,
 	io,
 	mem::ManuallyDrop,
+	os::fd::{AsRawFd, RawFd},
 	sync::{
-		Arc,
-		atomic::{AtomicU64, Ordering},
+		Arc, Mutex,
+		atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering},
 	},
-	task::{Context, Poll},
+	task::{Context, Poll, ready},
+	time::Duration,
 };
 
 use diatomic_waker::DiatomicWaker;
-use io_uring::squeue;
+use io_uring::{cqueue, opcode, squeue, types::Timespec};
 
-use crate::Result;
+use crate::{
+	Result,
+	compat::{BufferedRead, BufferedWrite},
+};
 
-use super::{UringData, resource::Resource};
+use super::{UringData, UringDataHandle};
 
 #[derive(Debug)]
 pub(crate) struct EventData {
@@ -21,6 +27,11 @@ pub(crate) struct EventData {
 	pub id: u32,
 }
 
+impl EventData {
+	/// User data for entries whose completions the worker should ignore.
+	pub const UNTRACKED: u64 = u64::MAX;
+}
+
 impl From<EventData> for u64 {
 	fn from(value: EventData) -> Self {
 		let EventData {
@@ -45,26 +56,53 @@ impl From<u64> for EventData {
 	}
 }
 
-#[derive(Debug)]
 #[repr(align(8))]
 pub(crate) struct OperationCancelData {
 	pub wake: bool,
-	pub buf: Vec<u8>,
+	// only kept alive until the kernel is done with it
+	#[expect(dead_code)]
+	pub buf: Box<dyn Send>,
+	/// Releases whatever a completion nobody will see hands over, like an accepted fd.
+	pub release: Option<fn(i32)>,
+}
+
+impl OperationCancelData {
+	fn release(&self, val: i32) {
+		if let Some(release) = self.release {
+			release(val);
+		}
+	}
 }
 
 pub(crate) enum OperationState {
 	Waiting,
 	Cancelled(ManuallyDrop<Box<OperationCancelData>>),
-	Finished(i32),
+	/// Result and flags of the completion.
+	Finished(i32, u32),
 }
 
 impl OperationState {
 	const TAG_SIZE: u64 = 0b111;
 
+	// cqe flags only use the low bits and the buffer id in the top 16 bits, which leaves room to
+	// pack them next to the result
+	const FLAGS_LOW: u32 = 0x1fff;
+
+	fn pack_flags(flags: u32) -> u64 {
+		u64::from(flags & Self::FLAGS_LOW) | (u64::from(flags >> 16) << 13)
+	}
+
+	fn unpack_flags(packed: u64) -> u32 {
+		// packed flags are only ever 29 bits
+		#[expect(clippy::cast_possible_truncation)]
+		let packed = packed as u32;
+		(packed & Self::FLAGS_LOW) | ((packed >> 13) << 16)
+	}
+
 	pub fn cancel_data(self) -> Option<ManuallyDrop<Box<OperationCancelData>>> {
 		match self {
 			Self::Cancelled(x) => Some(x),
-			Self::Waiting | Self::Finished(_) => None,
+			Self::Waiting | Self::Finished(..) => None,
 		}
 	}
 }
@@ -74,9 +112,9 @@ impl From<u64> for OperationState {
 		let (tag, data) = (value & Self::TAG_SIZE, value & !Self::TAG_SIZE);
 		match tag {
 			1 => Self::Waiting,
-			// data is only ever an i32
-			#[expect(clippy::cast_possible_truncation)]
-			2 => Self::Finished((data >> 3) as i32),
+			// data is an i32 followed by the packed flags
+			#[expect(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+			2 => Self::Finished((data >> 3) as u32 as i32, Self::unpack_flags(data >> 35)),
 			#[expect(clippy::cast_possible_truncation)]
 			3 => {
 				// SAFETY: data is only ever an 8 byte aligned pointer
@@ -96,7 +134,10 @@ impl From<OperationState> for u64 {
 			OperationState::Waiting => (0, 1u64),
 			// casted value is immediately casted back to i32
 			#[expect(clippy::cast_sign_loss)]
-			OperationState::Finished(val) => ((val as u64) << 3, 2u64),
+			OperationState::Finished(val, flags) => (
+				(u64::from(val as u32) << 3) | (OperationState::pack_flags(flags) << 35),
+				2u64,
+			),
 			OperationState::Cancelled(cleanup) => {
 				let addr = Box::into_raw(ManuallyDrop::into_inner(cleanup)).addr() as u64;
 				(addr, 3u64)
@@ -116,16 +157,65 @@ impl<const ID: u32, const SIZE: usize> AssertOperationBounds<ID, SIZE> {
 	const OK: () = assert!((ID as usize) < SIZE, "operation out of bounds");
 }
 
+/// Timeout linked to the last submission of an op. It lives in the op, so the kernel can read it
+/// for as long as the op is in flight, even after the handle that submitted it is gone.
+#[repr(C)]
+struct LinkedTimeout {
+	// laid out like `__kernel_timespec`
+	sec: AtomicI64,
+	nsec: AtomicI64,
+	linked: AtomicBool,
+}
+
+impl LinkedTimeout {
+	fn new() -> Self {
+		Self {
+			sec: AtomicI64::new(0),
+			nsec: AtomicI64::new(0),
+			linked: AtomicBool::new(false),
+		}
+	}
+
+	/// Only call this while the op isn't in flight, since the kernel may read the timespec.
+	fn set(&self, timeout: Option<Duration>) -> *const Timespec {
+		if let Some(timeout) = timeout {
+			self.sec.store(
+				i64::try_from(timeout.as_secs()).unwrap_or(i64::MAX),
+				Ordering::Relaxed,
+			);
+			self.nsec
+				.store(i64::from(timeout.subsec_nanos()), Ordering::Relaxed);
+		}
+		self.linked.store(timeout.is_some(), Ordering::Release);
+
+		(&raw const *self).cast()
+	}
+
+	fn linked(&self) -> bool {
+		self.linked.load(Ordering::Acquire)
+	}
+
+	fn unlink(&self) {
+		self.linked.store(false, Ordering::Release);
+	}
+}
+
 pub(crate) struct Operation<const SIZE: usize> {
 	state: AtomicU64,
 	waker: DiatomicWaker,
+	// completions of multishot ops that have more coming, and results of zero-copy sends waiting
+	// for their notification. the final one goes in the state
+	queue: Mutex<VecDeque<(i32, u32)>>,
+	timeout: LinkedTimeout,
 }
 
 impl<const SIZE: usize> Operation<SIZE> {
 	pub fn new() -> Self {
 		Self {
-			state: AtomicU64::new(OperationState::Finished(0).into()),
+			state: AtomicU64::new(OperationState::Finished(0, 0).into()),
 			waker: DiatomicWaker::new(),
+			queue: Mutex::new(VecDeque::new()),
+			timeout: LinkedTimeout::new(),
 		}
 	}
 
@@ -144,6 +234,7 @@ impl<const SIZE: usize> Operation<SIZE> {
 			Ordering::AcqRel,
 			Ordering::Acquire,
 		) {
+			// SAFETY: the worker never registers a waker
 			unsafe {
 				self.waker.unregister();
 			};
@@ -154,10 +245,45 @@ impl<const SIZE: usize> Operation<SIZE> {
 	}
 
 	#[inline(always)]
-	pub fn wake(&self, val: i32) {
+	fn register_waiting(&self, cx: &mut Context<'_>) {
+		let mut state = self.state();
+		while let Err(err) = self.register(state, cx) {
+			state = err;
+		}
+	}
+
+	/// Wait for a cancelled op to complete. Returns false if it already completed.
+	#[inline(always)]
+	pub fn wait_cancelled(&self, cx: &mut Context<'_>) -> bool {
+		// SAFETY: the worker never registers a waker
+		unsafe { self.waker.register(cx.waker()) };
+
+		matches!(self.state(), OperationState::Cancelled(_))
+	}
+
+	/// Complete the op. Returns true if it was cancelled, in which case nobody will see the result.
+	#[inline(always)]
+	pub fn wake(&self, val: i32, flags: u32) -> bool {
+		if cqueue::more(flags) {
+			// locked so a cancel can't slip in between checking the state and queueing
+			let mut queue = self.queue.lock().unwrap();
+			if let OperationState::Cancelled(cancel) = self.state() {
+				cancel.release(val);
+				return true;
+			}
+			queue.push_back((val, flags));
+			drop(queue);
+
+			self.waker.notify();
+			return false;
+		}
+
 		let state: OperationState = self
 			.state
-			.swap(OperationState::Finished(val).into(), Ordering::AcqRel)
+			.swap(
+				OperationState::Finished(val, flags).into(),
+				Ordering::AcqRel,
+			)
 			.into();
 		debug_assert!(matches!(
 			state,
@@ -171,7 +297,20 @@ impl<const SIZE: usize> Operation<SIZE> {
 
 		// drop anything that was needed for the op to complete safely
 		if let Some(mut cancel) = cancel {
+			cancel.release(val);
+			// SAFETY: the cancel data is owned by the state, which we just replaced
 			unsafe { ManuallyDrop::drop(&mut cancel) };
+			true
+		} else {
+			false
+		}
+	}
+
+	/// Report a cancel of the op in flight as `ECANCELED` instead of a timeout, for cancels we
+	/// submit ourselves. An op that already completed keeps its result.
+	pub fn unlink_timeout(&self) {
+		if matches!(self.state(), OperationState::Waiting) {
+			self.timeout.unlink();
 		}
 	}
 
@@ -198,21 +337,234 @@ impl<const SIZE: usize> Operation<SIZE> {
 				// we were waiting, task cancelled
 				true
 			}
-			OperationState::Finished(_) | OperationState::Cancelled(_) => {
+			OperationState::Finished(..) | OperationState::Cancelled(_) => {
 				// we were already done with the op or were already cancelled, drop our state
+				// SAFETY: our state was never stored, so we still own it
 				unsafe {
-					ManuallyDrop::drop(&mut OperationState::from(our_state).cancel_data().unwrap())
+					ManuallyDrop::drop(&mut OperationState::from(our_state).cancel_data().unwrap());
 				};
 				false
 			}
 		}
 	}
+
+	/// SAFETY: make sure entry will stay alive
+	unsafe fn submit(
+		&self,
+		submission: &mut OperationPollState,
+		rt: &UringData,
+		entry: &squeue::Entry,
+		timeout: Option<Duration>,
+		cx: &mut Context,
+	) -> Result<()> {
+		self.register_waiting(cx);
+
+		let timespec = self.timeout.set(timeout);
+		if timeout.is_some() {
+			let entries = [
+				entry.clone().flags(squeue::Flags::IO_LINK),
+				opcode::LinkTimeout::new(timespec)
+					.build()
+					.user_data(EventData::UNTRACKED),
+			];
+			// SAFETY: enforced by caller, and the timespec lives as long as the op
+			unsafe { rt.submit_all(&entries)? };
+		} else {
+			// SAFETY: enforced by caller
+			unsafe { rt.submit(entry)? };
+		}
+		*submission = OperationPollState::Submitting;
+
+		Ok(())
+	}
+
+	/// Poll a multishot op, which yields every completion and finishes after the one without
+	/// `IORING_CQE_F_MORE`.
+	fn poll_multi(
+		&self,
+		submission: &mut OperationPollState,
+		cx: &mut Context,
+	) -> Poll<Option<Result<(u32, u32)>>> {
+		let ret = match *submission {
+			OperationPollState::Idle if self.wait_cancelled(cx) => return Poll::Pending,
+			OperationPollState::Idle => return Poll::Ready(None),
+			OperationPollState::Notifying(_) => {
+				unreachable!("polled a zero-copy send as multishot")
+			}
+			OperationPollState::Submitting => {
+				// SAFETY: the worker never registers a waker
+				unsafe { self.waker.register(cx.waker()) };
+
+				// the final completion is only stored after every queued one
+				let state = self.state();
+				if let Some(ret) = self.queue.lock().unwrap().pop_front() {
+					ret
+				} else if let OperationState::Finished(val, flags) = state {
+					*submission = OperationPollState::Idle;
+					(val, flags)
+				} else {
+					return Poll::Pending;
+				}
+			}
+		};
+
+		Poll::Ready(Some(match ret {
+			(val, _) if val < 0 => Err(io::Error::from_raw_os_error(-val).into()),
+			// we already check if it's below 0
+			#[expect(clippy::cast_sign_loss)]
+			(val, flags) => Ok((val as u32, flags)),
+		}))
+	}
+
+	/// Poll a zero-copy send, which completes once with its result and, if that has
+	/// `IORING_CQE_F_MORE`, again with `IORING_CQE_F_NOTIF` once the kernel is done with the
+	/// buffer. The result is only returned after the notification, so the buffer can't be
+	/// released early.
+	fn poll_notif(
+		&self,
+		submission: &mut OperationPollState,
+		cx: &mut Context,
+	) -> Poll<Option<Result<u32>>> {
+		let ret = match *submission {
+			OperationPollState::Idle if self.wait_cancelled(cx) => return Poll::Pending,
+			OperationPollState::Idle => return Poll::Ready(None),
+			OperationPollState::Submitting | OperationPollState::Notifying(_) => {
+				// SAFETY: the worker never registers a waker
+				unsafe { self.waker.register(cx.waker()) };
+
+				// the notification is only stored after the result is queued
+				let state = self.state();
+				if let Some((val, _)) = self.queue.lock().unwrap().pop_front() {
+					*submission = OperationPollState::Notifying(val);
+				}
+
+				match (*submission, state) {
+					(_, OperationState::Waiting) => return Poll::Pending,
+					(OperationPollState::Notifying(val), OperationState::Finished(..))
+					| (_, OperationState::Finished(val, _)) => val,
+					(_, OperationState::Cancelled(_)) => {
+						unreachable!("cancelling an op makes its submission idle")
+					}
+				}
+			}
+		};
+		*submission = OperationPollState::Idle;
+
+		Poll::Ready(Some(if ret < 0 {
+			Err(io::Error::from_raw_os_error(-ret).into())
+		} else {
+			// we already check if it's below 0
+			#[expect(clippy::cast_sign_loss)]
+			Ok(ret as u32)
+		}))
+	}
+
+	fn poll_submit(
+		&self,
+		submission: &mut OperationPollState,
+		cx: &mut Context,
+	) -> Poll<Option<Result<u32>>> {
+		macro_rules! finish {
+			($ret:expr) => {
+				*submission = OperationPollState::Idle;
+
+				// the linked timeout fired and cancelled the op
+				let ret = if $ret == -libc::ECANCELED && self.timeout.linked() {
+					-libc::ETIMEDOUT
+				} else {
+					$ret
+				};
+				if ret < 0 {
+					return Poll::Ready(Some(Err(io::Error::from_raw_os_error(-ret).into())));
+				} else {
+					// we already check if it's below 0
+					#[expect(clippy::cast_sign_loss)]
+					return Poll::Ready(Some(Ok(ret as u32)));
+				}
+			};
+		}
+
+		match *submission {
+			// a cancelled submission is still in flight, wait for it so it can't steal the
+			// completion of the next one
+			OperationPollState::Idle if self.wait_cancelled(cx) => Poll::Pending,
+			OperationPollState::Idle => Poll::Ready(None),
+			// a zero-copy send whose future was leaked, its result has nobody to go to once the
+			// kernel released the buffer
+			OperationPollState::Notifying(_) => {
+				let _ = ready!(self.poll_notif(submission, cx));
+				Poll::Ready(None)
+			}
+			OperationPollState::Submitting => match self.state() {
+				OperationState::Finished(ret, _) => {
+					finish!(ret);
+				}
+				OperationState::Waiting => match self.register(OperationState::Waiting, cx) {
+					Ok(()) | Err(OperationState::Waiting) => Poll::Pending,
+					Err(OperationState::Finished(ret, _)) => {
+						finish!(ret);
+					}
+					Err(OperationState::Cancelled(_)) => {
+						todo!();
+					}
+				},
+				OperationState::Cancelled(_) => {
+					todo!("implement polling an operation that was cancelled");
+				}
+			},
+		}
+	}
+
+	/// Flags of the last completion, valid right after `poll_submit` returned it.
+	fn flags(&self) -> u32 {
+		match self.state() {
+			OperationState::Finished(_, flags) => flags,
+			OperationState::Waiting | OperationState::Cancelled(_) => 0,
+		}
+	}
+
+	/// Cancel the op, returning true if it's still in flight.
+	///
+	/// Completions that arrived but were never polled are handed to `leftover`, since nobody
+	/// will see them anymore.
+	fn try_cancel(
+		&self,
+		submission: &mut OperationPollState,
+		data: OperationCancelData,
+		mut leftover: impl FnMut(i32, u32),
+	) -> bool {
+		// locked so the worker can't queue anything after we drain it
+		let mut queue = self.queue.lock().unwrap();
+		let in_flight = self.cancel(data);
+		for (val, flags) in queue.drain(..) {
+			leftover(val, flags);
+		}
+		drop(queue);
+
+		if in_flight {
+			*submission = OperationPollState::Idle;
+			true
+		} else {
+			if !matches!(submission, OperationPollState::Idle)
+				&& let OperationState::Finished(val, flags) = self.state()
+			{
+				// the op completed but nobody polled it, so its result would go to the next
+				// submission
+				*submission = OperationPollState::Idle;
+				leftover(val, flags);
+			}
+			false
+		}
+	}
 }
 
 #[derive(Copy, Clone)]
 pub(crate) enum OperationPollState {
 	Idle,
 	Submitting,
+	/// A zero-copy send delivered this result, but the kernel still holds the buffer until its
+	/// notification arrives.
+	Notifying(i32),
 }
 
 pub(crate) struct Operations<const SIZE: usize = 4> {
@@ -248,89 +600,243 @@ impl<const SIZE: usize> Operations<SIZE> {
 		}
 	}
 
+	/// Take over the submission state of the ops `other` tracks and we don't, for handles that
+	/// are put back together.
+	pub fn merge(&mut self, other: &Self) {
+		for (ours, theirs) in self.submissions.iter_mut().zip(other.submissions) {
+			if matches!(ours, OperationPollState::Idle) {
+				*ours = theirs;
+			}
+		}
+	}
+
+	/// Whether both handles belong to the same resource.
+	pub fn same(&self, other: &Self) -> bool {
+		Arc::ptr_eq(&self.ops, &other.ops)
+	}
+
+	/// Like `dup`, but only keeps the submission state of `ids`, for handles that each poll
+	/// their own ops.
+	pub fn dup_only(&self, ids: &[u32]) -> Self {
+		let mut submissions = [OperationPollState::Idle; SIZE];
+		for &id in ids {
+			submissions[id as usize] = self.submissions[id as usize];
+		}
+		Self {
+			ops: self.ops.clone(),
+			submissions,
+		}
+	}
+
 	/// SAFETY: make sure entry will stay alive
 	pub unsafe fn start_submit<const ID: u32>(
 		&mut self,
 		rt: &UringData,
 		entry: &squeue::Entry,
+		timeout: Option<Duration>,
 		cx: &mut Context,
 	) -> Result<()> {
 		let () = AssertOperationBounds::<ID, SIZE>::OK;
-		let op = &self.ops[ID as usize];
 		let submission = &mut self.submissions[ID as usize];
+		// SAFETY: enforced by caller
+		unsafe { self.ops[ID as usize].submit(submission, rt, entry, timeout, cx) }
+	}
 
-		let mut state = op.state();
-		while let Err(err) = op.register(state, cx) {
-			state = err;
-		}
+	/// Mark an op as submitted before its entry is submitted as part of a batch.
+	///
+	/// If submitting the batch fails, `abort_submit` must be called.
+	pub fn prepare_submit(&mut self, id: u32, cx: &mut Context) {
+		// a timeout linked to the op's last submission doesn't apply to this one
+		self.ops[id as usize].timeout.unlink();
+		self.ops[id as usize].register_waiting(cx);
+		self.submissions[id as usize] = OperationPollState::Submitting;
+	}
 
-		// SAFETY: enforced by caller
-		unsafe { rt.submit(entry)? };
-		*submission = OperationPollState::Submitting;
+	/// Undo `prepare_submit` for an op whose entry was never submitted.
+	pub fn abort_submit(&mut self, id: u32) {
+		self.ops[id as usize]
+			.state
+			.store(OperationState::Finished(0, 0).into(), Ordering::Release);
+		self.submissions[id as usize] = OperationPollState::Idle;
+	}
 
-		Ok(())
+	/// Submission state of an op as seen by this handle.
+	pub fn submission(&self, id: u32) -> OperationPollState {
+		self.submissions[id as usize]
 	}
 
-	pub fn poll_submit<const ID: u32>(&mut self, cx: &mut Context) -> Poll<Option<Result<u32>>> {
+	/// Borrow the submission state of two disjoint sets of ops, for handles that poll them in
+	/// our place while they live.
+	pub fn split_mut<const A: usize, const B: usize>(
+		&mut self,
+		a: [u32; A],
+		b: [u32; B],
+	) -> (
+		BorrowedOperations<'_, A, SIZE>,
+		BorrowedOperations<'_, B, SIZE>,
+	) {
+		let mut slots = self.submissions.each_mut().map(Some);
+		let mut take = |id: u32| {
+			let slot = slots.get_mut(id as usize).and_then(Option::take);
+			(id, slot.expect("ops are distinct and in bounds"))
+		};
+		let (a, b) = (a.map(&mut take), b.map(&mut take));
+
+		(
+			BorrowedOperations {
+				ops: &self.ops,
+				slots: a,
+			},
+			BorrowedOperations {
+				ops: &self.ops,
+				slots: b,
+			},
+		)
+	}
+
+	pub fn poll_multi<const ID: u32>(
+		&mut self,
+		cx: &mut Context,
+	) -> Poll<Option<Result<(u32, u32)>>> {
 		let () = AssertOperationBounds::<ID, SIZE>::OK;
-		let op = &self.ops[ID as usize];
-		let submission = &mut self.submissions[ID as usize];
+		self.poll_multi_id(ID, cx)
+	}
 
-		macro_rules! finish {
-			($ret:expr) => {
-				*submission = OperationPollState::Idle;
+	pub fn poll_multi_id(&mut self, id: u32, cx: &mut Context) -> Poll<Option<Result<(u32, u32)>>> {
+		self.ops[id as usize].poll_multi(&mut self.submissions[id as usize], cx)
+	}
 
-				if $ret < 0 {
-					return Poll::Ready(Some(Err(io::Error::from_raw_os_error(-$ret).into())));
-				} else {
-					// we already check if it's below 0
-					#[expect(clippy::cast_sign_loss)]
-					return Poll::Ready(Some(Ok($ret as u32)));
-				}
-			};
-		}
+	pub fn poll_notif<const ID: u32>(&mut self, cx: &mut Context) -> Poll<Option<Result<u32>>> {
+		let () = AssertOperationBounds::<ID, SIZE>::OK;
+		self.poll_notif_id(ID, cx)
+	}
 
-		match *submission {
-			OperationPollState::Idle => Poll::Ready(None),
-			OperationPollState::Submitting => match op.state() {
-				OperationState::Finished(ret) => {
-					finish!(ret);
-				}
-				OperationState::Waiting => match op.register(OperationState::Waiting, cx) {
-					Ok(()) | Err(OperationState::Waiting) => Poll::Pending,
-					Err(OperationState::Finished(ret)) => {
-						finish!(ret);
-					}
-					Err(OperationState::Cancelled(_)) => {
-						todo!();
-					}
-				},
-				OperationState::Cancelled(_) => {
-					todo!("implement polling an operation that was cancelled");
-				}
-			},
-		}
+	pub fn poll_notif_id(&mut self, id: u32, cx: &mut Context) -> Poll<Option<Result<u32>>> {
+		self.ops[id as usize].poll_notif(&mut self.submissions[id as usize], cx)
+	}
+
+	pub fn poll_submit<const ID: u32>(&mut self, cx: &mut Context) -> Poll<Option<Result<u32>>> {
+		let () = AssertOperationBounds::<ID, SIZE>::OK;
+		self.poll_submit_id(ID, cx)
 	}
 
 	// this isn't possible to constify without generic_const_exprs
-	pub fn try_cancel(&mut self, id: u32, data: OperationCancelData) -> bool {
-		let op = &self.ops[id as usize];
-		let submission = &mut self.submissions[id as usize];
+	pub fn poll_submit_id(&mut self, id: u32, cx: &mut Context) -> Poll<Option<Result<u32>>> {
+		self.ops[id as usize].poll_submit(&mut self.submissions[id as usize], cx)
+	}
 
-		if op.cancel(data) {
-			*submission = OperationPollState::Idle;
-			true
-		} else {
-			false
-		}
+	/// Flags of the last completion of an op, valid right after `poll_submit` returned it.
+	pub fn flags(&self, id: u32) -> u32 {
+		self.ops[id as usize].flags()
+	}
+
+	/// Cancel an op, returning true if it's still in flight.
+	///
+	/// Completions that arrived but were never polled are handed to `leftover`, since nobody
+	/// will see them anymore.
+	// this isn't possible to constify without generic_const_exprs
+	pub fn try_cancel(
+		&mut self,
+		id: u32,
+		data: OperationCancelData,
+		leftover: impl FnMut(i32, u32),
+	) -> bool {
+		self.ops[id as usize].try_cancel(&mut self.submissions[id as usize], data, leftover)
 	}
 
 	pub fn get(&self, id: u32) -> Option<&Operation<SIZE>> {
 		self.ops.get(id as usize)
 	}
 
-	pub fn poll_states(&mut self) -> impl Iterator<Item = &mut OperationPollState> {
-		self.submissions.iter_mut()
+	/// Whether the kernel might still complete any op, including cancelled ones.
+	pub fn in_flight(&self) -> bool {
+		self.submissions
+			.iter()
+			.any(|x| !matches!(x, OperationPollState::Idle))
+			|| self
+				.ops
+				.iter()
+				.any(|x| matches!(x.state(), OperationState::Cancelled(_)))
+	}
+}
+
+/// Some ops of a handle, borrowed along with its submission state so they are polled in its
+/// place and nothing has to be copied back.
+pub(crate) struct BorrowedOperations<'a, const N: usize, const SIZE: usize = 4> {
+	ops: &'a [Operation<SIZE>; SIZE],
+	slots: [(u32, &'a mut OperationPollState); N],
+}
+
+impl<const N: usize, const SIZE: usize> BorrowedOperations<'_, N, SIZE> {
+	fn slot(&mut self, id: u32) -> (&Operation<SIZE>, &mut OperationPollState) {
+		let (_, submission) = self
+			.slots
+			.iter_mut()
+			.find(|(x, _)| *x == id)
+			.expect("op is borrowed");
+		(&self.ops[id as usize], submission)
+	}
+
+	/// SAFETY: make sure entry will stay alive
+	pub unsafe fn start_submit<const ID: u32>(
+		&mut self,
+		rt: &UringData,
+		entry: &squeue::Entry,
+		timeout: Option<Duration>,
+		cx: &mut Context,
+	) -> Result<()> {
+		let () = AssertOperationBounds::<ID, SIZE>::OK;
+		let (op, submission) = self.slot(ID);
+		// SAFETY: enforced by caller
+		unsafe { op.submit(submission, rt, entry, timeout, cx) }
+	}
+
+	pub fn submission(&self, id: u32) -> OperationPollState {
+		let (_, submission) = self
+			.slots
+			.iter()
+			.find(|(x, _)| *x == id)
+			.expect("op is borrowed");
+		**submission
+	}
+
+	pub fn poll_multi<const ID: u32>(
+		&mut self,
+		cx: &mut Context,
+	) -> Poll<Option<Result<(u32, u32)>>> {
+		let () = AssertOperationBounds::<ID, SIZE>::OK;
+		let (op, submission) = self.slot(ID);
+		op.poll_multi(submission, cx)
+	}
+
+	pub fn poll_notif<const ID: u32>(&mut self, cx: &mut Context) -> Poll<Option<Result<u32>>> {
+		let () = AssertOperationBounds::<ID, SIZE>::OK;
+		let (op, submission) = self.slot(ID);
+		op.poll_notif(submission, cx)
+	}
+
+	pub fn poll_submit<const ID: u32>(&mut self, cx: &mut Context) -> Poll<Option<Result<u32>>> {
+		let () = AssertOperationBounds::<ID, SIZE>::OK;
+		let (op, submission) = self.slot(ID);
+		op.poll_submit(submission, cx)
+	}
+
+	pub fn flags(&self, id: u32) -> u32 {
+		self.ops[id as usize].flags()
+	}
+
+	pub fn try_cancel(
+		&mut self,
+		id: u32,
+		data: OperationCancelData,
+		leftover: impl FnMut(i32, u32),
+	) -> bool {
+		let (op, submission) = self.slot(id);
+		op.try_cancel(submission, data, leftover)
+	}
+
+	pub fn get(&self, id: u32) -> Option<&Operation<SIZE>> {
+		self.ops.get(id as usize)
 	}
 }
 
@@ -339,6 +845,45 @@ macro_rules! poll_op_impl {
 	($id:expr, $this:expr, $cx:expr, $ignore_closing:expr, {
 		Some(Ok(val)) => $ok:expr,
 		None => $new:expr
+	}) => {
+		$crate::rt::operation::poll_op_impl!(@poll poll_submit, None, $id, $this, $cx, $ignore_closing, {
+			Some(Ok(val)) => $ok,
+			None => $new
+		})
+	};
+	// ops that fail with `ETIMEDOUT` if they don't complete within an `Option<Duration>`
+	(timeout $timeout:expr, $id:expr, $this:expr, $cx:expr, $ignore_closing:expr, {
+		Some(Ok(val)) => $ok:expr,
+		None => $new:expr
+	}) => {
+		$crate::rt::operation::poll_op_impl!(@poll poll_submit, $timeout, $id, $this, $cx, $ignore_closing, {
+			Some(Ok(val)) => $ok,
+			None => $new
+		})
+	};
+	// zero-copy sends yield their result once the buffer is released
+	(zerocopy $id:expr, $this:expr, $cx:expr, $ignore_closing:expr, {
+		Some(Ok(val)) => $ok:expr,
+		None => $new:expr
+	}) => {
+		$crate::rt::operation::poll_op_impl!(@poll poll_notif, None, $id, $this, $cx, $ignore_closing, {
+			Some(Ok(val)) => $ok,
+			None => $new
+		})
+	};
+	// multishot ops yield every completion as (result, flags)
+	(multishot $id:expr, $this:expr, $cx:expr, $ignore_closing:expr, {
+		Some(Ok(val)) => $ok:expr,
+		None => $new:expr
+	}) => {
+		$crate::rt::operation::poll_op_impl!(@poll poll_multi, None, $id, $this, $cx, $ignore_closing, {
+			Some(Ok(val)) => $ok,
+			None => $new
+		})
+	};
+	(@poll $poll:ident, $timeout:expr, $id:expr, $this:expr, $cx:expr, $ignore_closing:expr, {
+		Some(Ok(val)) => $ok:expr,
+		None => $new:expr
 	}) => {
 		(|| {
 			use std::task::Poll;
@@ -349,7 +894,7 @@ macro_rules! poll_op_impl {
 			};
 
 			let id = $this.resource.id;
-			return match ready!($this.resource.ops.poll_submit::<{ $id }>($cx)) {
+			return match ready!($this.resource.ops.$poll::<{ $id }>($cx)) {
 				Some(Ok(val)) => ($ok)(val),
 				Some(Err(err)) => Poll::Ready(Err(err)),
 				None => {
@@ -362,7 +907,7 @@ macro_rules! poll_op_impl {
 							Err(err) => return Poll::Ready(Err(err)),
 						};
 
-						let entry = val.build().user_data(
+						let entry = val.user_data(
 							EventData {
 								resource: id,
 								id: $id,
@@ -373,7 +918,7 @@ macro_rules! poll_op_impl {
 						if let Err(err) =
 							// SAFETY: enforced by the caller
 							unsafe {
-								$this.resource.ops.start_submit::<{ $id }>(rt, &entry, $cx)
+								$this.resource.ops.start_submit::<{ $id }>(rt, &entry, $timeout, $cx)
 							} {
 							Poll::Ready(Err(err))
 						} else {
@@ -388,7 +933,275 @@ macro_rules! poll_op_impl {
 pub(crate) use poll_op_impl;
 
 pub(crate) trait ProtectedOps {
-	fn get_resource(&mut self) -> &mut Resource;
+	fn get_rt(&self) -> &UringDataHandle;
 	const READ_OP_ID: u32;
 	const WRITE_OP_ID: u32;
+
+	/// Cancel an op, keeping `data` alive until the kernel is done with it.
+	fn cancel_op(&mut self, id: u32, data: OperationCancelData);
+
+	/// Stop waiting for an op without cancelling it, keeping `data` alive until it completes.
+	fn detach_op(&mut self, id: u32, data: OperationCancelData);
+
+	/// Submission state of an op as seen by this handle.
+	fn submission(&self, id: u32) -> OperationPollState;
+
+	/// Ask the kernel to cancel an op we keep waiting for, see [`Resource::interrupt`].
+	///
+	/// [`Resource::interrupt`]: super::resource::Resource::interrupt
+	fn interrupt_op(&self, id: u32);
+
+	/// Cancel whatever a leaked future left in flight on op `id`, so the next op can't take its
+	/// completion. Its buffer was leaked along with it, so the kernel can keep using it.
+	fn reclaim_op(&mut self, id: u32) {
+		if !matches!(self.submission(id), OperationPollState::Idle) {
+			self.cancel_op(
+				id,
+				OperationCancelData {
+					wake: true,
+					buf: Box::new(()),
+					release: None,
+				},
+			);
+		}
+	}
+
+	/// Hand whatever a leaked or dropped future left in flight on op `id` to the runtime without
+	/// cancelling it, so closing the resource from another handle waits for it.
+	fn release_op(&mut self, id: u32) {
+		if !matches!(self.submission(id), OperationPollState::Idle) {
+			self.detach_op(
+				id,
+				OperationCancelData {
+					wake: true,
+					buf: Box::new(()),
+					release: None,
+				},
+			);
+		}
+	}
+}
+
+pub(crate) trait RawRead: ProtectedOps {
+	/// Read into `ptr`, using `ReadFixed` if `buf_index` names the registered buffer it lives in.
+	///
+	/// SAFETY: make sure the buffer stays alive until the op completes
+	unsafe fn poll_read_raw(
+		&mut self,
+		cx: &mut Context<'_>,
+		ptr: *mut u8,
+		len: usize,
+		buf_index: Option<u16>,
+	) -> Poll<Result<usize>>;
+}
+
+pub(crate) trait FlaggedRead: ProtectedOps {
+	/// Receive into `ptr` with `recv` flags like `MSG_PEEK`.
+	///
+	/// SAFETY: make sure the buffer stays alive until the op completes
+	unsafe fn poll_recv_flags(
+		&mut self,
+		cx: &mut Context<'_>,
+		ptr: *mut u8,
+		len: usize,
+		flags: i32,
+	) -> Poll<Result<usize>>;
+}
+
+pub(crate) trait ProvidedRead: ProtectedOps {
+	/// Receive into a buffer the kernel picks from the runtime's provided buffer ring, returning
+	/// the result and completion flags.
+	fn poll_read_provided(&mut self, cx: &mut Context<'_>) -> Poll<Result<(u32, u32)>>;
+
+	/// Like `poll_read_provided`, but with a multishot recv that completes again every time data
+	/// arrives, until the kernel drops `IORING_CQE_F_MORE`. The next poll after that re-arms it.
+	fn poll_recv_multi(&mut self, cx: &mut Context<'_>) -> Poll<Result<(u32, u32)>>;
+}
+
+pub(crate) trait RawWrite: ProtectedOps {
+	/// Write from `ptr`, using `WriteFixed` if `buf_index` names the registered buffer it lives in.
+	///
+	/// SAFETY: make sure the buffer stays alive until the op completes
+	unsafe fn poll_write_raw(
+		&mut self,
+		cx: &mut Context<'_>,
+		ptr: *const u8,
+		len: usize,
+		buf_index: Option<u16>,
+	) -> Poll<Result<usize>>;
+}
+
+pub(crate) trait VectoredRead: ProtectedOps {
+	/// Read into the `cnt` buffers `iov` points to, filling each before moving on to the next.
+	///
+	/// SAFETY: make sure the iovecs and the buffers they point to stay alive until the op completes
+	unsafe fn poll_readv_raw(
+		&mut self,
+		cx: &mut Context<'_>,
+		iov: *const libc::iovec,
+		cnt: u32,
+	) -> Poll<Result<usize>>;
+}
+
+pub(crate) trait VectoredWrite: ProtectedOps {
+	/// Write from the `cnt` buffers `iov` points to, in order, with a single op.
+	///
+	/// SAFETY: make sure the iovecs and the buffers they point to stay alive until the op completes
+	unsafe fn poll_writev_raw(
+		&mut self,
+		cx: &mut Context<'_>,
+		iov: *const libc::iovec,
+		cnt: u32,
+	) -> Poll<Result<usize>>;
+}
+
+pub(crate) trait Splice: BufferedRead + BufferedWrite {
+	/// Splice up to `len` bytes from the socket into the write end of `pipe`.
+	///
+	/// SAFETY: make sure the pipe stays open until the op completes
+	unsafe fn poll_splice_in(
+		&mut self,
+		cx: &mut Context<'_>,
+		pipe: RawFd,
+		len: u32,
+	) -> Poll<Result<usize>>;
+
+	/// Splice up to `len` bytes from the read end of `pipe` into the socket.
+	///
+	/// SAFETY: make sure the pipe stays open until the op completes
+	unsafe fn poll_splice_out(
+		&mut self,
+		cx: &mut Context<'_>,
+		pipe: RawFd,
+		len: u32,
+	) -> Poll<Result<usize>>;
+
+	/// Wait until the socket is readable, on the read op.
+	fn poll_readable(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>>;
+
+	/// Wait until the socket is writable, on the write op.
+	fn poll_writable(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>>;
+
+	/// Shut down the write direction once everything was sent.
+	fn poll_shutdown_write(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>>;
+}
+
+pub(crate) trait RawSockOpt: BufferedWrite + AsRawFd {
+	/// Set a socket option, or get it with `get`, through the ring. Uses the write op, so it's
+	/// ordered with sends.
+	///
+	/// SAFETY: make sure the value stays alive until the op completes
+	unsafe fn poll_sockopt(
+		&mut self,
+		cx: &mut Context<'_>,
+		get: bool,
+		level: i32,
+		name: i32,
+		val: *mut libc::c_void,
+		len: u32,
+	) -> Poll<Result<usize>>;
+}
+
+pub(crate) trait ZeroCopyWrite: BufferedWrite {
+	/// Send from `ptr` without copying it into the kernel, completing once the kernel is done
+	/// with the buffer. Uses the registered buffer `buf_index` names, if any.
+	///
+	/// SAFETY: make sure the buffer stays alive until the op completes
+	unsafe fn poll_send_zc(
+		&mut self,
+		cx: &mut Context<'_>,
+		ptr: *const u8,
+		len: usize,
+		buf_index: Option<u16>,
+	) -> Poll<Result<usize>>;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// IORING_CQE_F_* from io_uring.h
+	const F_BUFFER: u32 = 1 << 0;
+	const F_MORE: u32 = 1 << 1;
+	const F_SOCK_NONEMPTY: u32 = 1 << 2;
+	const F_NOTIF: u32 = 1 << 3;
+	// in the result of a zero-copy notification, if the kernel copied the data after all
+	const NOTIF_USAGE_ZC_COPIED: i32 = i32::MIN;
+
+	fn round_trip(res: i32, flags: u32) -> (i32, u32) {
+		match OperationState::from(u64::from(OperationState::Finished(res, flags))) {
+			OperationState::Finished(res, flags) => (res, flags),
+			_ => panic!("finished op came back in another state"),
+		}
+	}
+
+	#[test]
+	fn finished_keeps_result() {
+		for res in [0, 1, i32::MAX, -libc::ECANCELED, i32::MIN] {
+			assert_eq!(round_trip(res, 0), (res, 0));
+		}
+	}
+
+	#[test]
+	fn finished_keeps_buffer_id() {
+		for bid in [0, 1, 0x1234, u16::MAX] {
+			let flags = F_BUFFER | F_MORE | F_SOCK_NONEMPTY | (u32::from(bid) << 16);
+			let (res, unpacked) = round_trip(-1, flags);
+
+			assert_eq!((res, unpacked), (-1, flags));
+			assert_eq!(cqueue::buffer_select(unpacked), Some(bid));
+			assert!(cqueue::more(unpacked));
+			assert!(cqueue::sock_nonempty(unpacked));
+		}
+	}
+
+	#[test]
+	fn low_flags_fit_next_to_buffer_id() {
+		let flags = OperationState::FLAGS_LOW | (u32::from(u16::MAX) << 16);
+		assert_eq!(round_trip(i32::MAX, flags), (i32::MAX, flags));
+		assert_eq!(OperationState::pack_flags(flags) >> 29, 0);
+	}
+	#[test]
+	fn notif_keeps_zc_copied() {
+		let (res, flags) = round_trip(NOTIF_USAGE_ZC_COPIED, F_NOTIF);
+		assert_eq!(res, NOTIF_USAGE_ZC_COPIED);
+		assert!(cqueue::notif(flags));
+		assert!(!cqueue::more(flags));
+	}
+
+	#[test]
+	fn notif_returns_send_result() {
+		let mut ops = Operations::<1>::new_from_size();
+		let mut cx = Context::from_waker(std::task::Waker::noop());
+		ops.prepare_submit(0, &mut cx);
+
+		assert!(!ops.ops[0].wake(5, F_MORE));
+		// the buffer is still in use until the notification
+		assert!(ops.poll_notif_id(0, &mut cx).is_pending());
+		assert!(matches!(
+			ops.submission(0),
+			OperationPollState::Notifying(5)
+		));
+
+		assert!(!ops.ops[0].wake(NOTIF_USAGE_ZC_COPIED, F_NOTIF));
+		assert!(matches!(
+			ops.poll_notif_id(0, &mut cx),
+			Poll::Ready(Some(Ok(5)))
+		));
+		assert!(matches!(ops.submission(0), OperationPollState::Idle));
+	}
+
+	#[test]
+	fn send_without_notif() {
+		let mut ops = Operations::<1>::new_from_size();
+		let mut cx = Context::from_waker(std::task::Waker::noop());
+		ops.prepare_submit(0, &mut cx);
+
+		// a failed send never gets a notification
+		assert!(!ops.ops[0].wake(-libc::EPIPE, 0));
+		let Poll::Ready(Some(Err(err))) = ops.poll_notif_id(0, &mut cx) else {
+			panic!("failed send is still pending");
+		};
+		assert_eq!(io::Error::from(err).raw_os_error(), Some(libc::EPIPE));
+	}
 }
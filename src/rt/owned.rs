@@ -0,0 +1,766 @@
+use std::{
+	io,
+	pin::Pin,
+	task::{Context, Poll, ready},
+};
+
+use futures::Stream;
+use io_uring::cqueue;
+
+use crate::{
+	Error,
+	buf::{IoBuf, IoBufMut, ProvidedBuf},
+	compat::{BufferedRead, BufferedWrite, ReadBuffer, WriteBuffer},
+	net::sockopt,
+};
+
+use super::operation::{
+	FlaggedRead, OperationCancelData, ProvidedRead, RawSockOpt, VectoredRead, VectoredWrite,
+	ZeroCopyWrite,
+};
+
+pub(crate) struct OwnedRead<'a, T: BufferedRead, B: IoBufMut> {
+	io: &'a mut T,
+	buf: Option<B>,
+	submitted: bool,
+}
+
+impl<'a, T: BufferedRead, B: IoBufMut> OwnedRead<'a, T, B> {
+	pub fn new(io: &'a mut T, buf: B) -> Self {
+		Self {
+			io,
+			buf: Some(buf),
+			submitted: false,
+		}
+	}
+}
+
+impl<T: BufferedRead, B: IoBufMut> Future for OwnedRead<'_, T, B> {
+	type Output = (io::Result<usize>, B);
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		let this = &mut *self;
+		let buf = this.buf.as_mut().expect("polled after completion");
+
+		if !this.submitted {
+			let ret = ready!(ReadBuffer::poll_prepare(this.io, cx));
+			let read_buf = this.io.read_buffer();
+			if ret.is_err() || !read_buf.is_empty() {
+				let mut buf = this.buf.take().unwrap();
+				// SAFETY: the buffer has room for its total capacity
+				let ret = ret.map(|()| unsafe {
+					read_buf.copy_out(buf.stable_mut_ptr(), buf.bytes_total(), false)
+				});
+				if let Ok(cnt) = ret {
+					// SAFETY: just copied these bytes in
+					unsafe { buf.set_init(cnt) };
+				}
+				return Poll::Ready((ret, buf));
+			}
+		}
+
+		let (ptr, len, index) = (buf.stable_mut_ptr(), buf.bytes_total(), buf.buf_index());
+		this.submitted = true;
+		// SAFETY: the buffer is handed to the runtime if we are dropped before the op completes
+		let ret = ready!(unsafe { this.io.poll_read_raw(cx, ptr, len, index) });
+
+		let mut buf = this.buf.take().unwrap();
+		if let Ok(cnt) = ret {
+			// SAFETY: kernel just initialized these bytes in the read op
+			unsafe { buf.set_init(cnt) };
+		}
+
+		Poll::Ready((ret.map_err(Into::into), buf))
+	}
+}
+
+impl<T: BufferedRead, B: IoBufMut> Drop for OwnedRead<'_, T, B> {
+	fn drop(&mut self) {
+		// the op may belong to someone else until we submitted ours
+		if self.submitted
+			&& let Some(buf) = self.buf.take()
+		{
+			self.io.cancel_op(
+				T::READ_OP_ID,
+				OperationCancelData {
+					wake: true,
+					buf: Box::new(buf),
+					release: None,
+				},
+			);
+		}
+	}
+}
+
+pub(crate) struct OwnedRecv<'a, T: FlaggedRead + BufferedRead, B: IoBufMut> {
+	io: &'a mut T,
+	buf: Option<B>,
+	flags: i32,
+	// keep receiving until the buffer is full, `MSG_WAITALL` can still come back short
+	exact: bool,
+	pos: usize,
+	submitted: bool,
+}
+
+impl<'a, T: FlaggedRead + BufferedRead, B: IoBufMut> OwnedRecv<'a, T, B> {
+	pub fn new(io: &'a mut T, buf: B, flags: i32, exact: bool) -> Self {
+		Self {
+			io,
+			buf: Some(buf),
+			flags,
+			exact,
+			pos: 0,
+			submitted: false,
+		}
+	}
+}
+
+impl<T: FlaggedRead + BufferedRead, B: IoBufMut> Future for OwnedRecv<'_, T, B> {
+	type Output = (io::Result<usize>, B);
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		let this = &mut *self;
+		if !this.submitted {
+			let buf = this.buf.as_mut().expect("polled after completion");
+			let ret = ready!(ReadBuffer::poll_prepare(this.io, cx));
+			let read_buf = this.io.read_buffer();
+			// out-of-band data never went through the buffer
+			if ret.is_err() || (this.flags & libc::MSG_OOB == 0 && !read_buf.is_empty()) {
+				let total = buf.bytes_total();
+				let peek = this.flags & libc::MSG_PEEK != 0;
+				// SAFETY: the buffer has room for its total capacity
+				let ret =
+					ret.map(|()| unsafe { read_buf.copy_out(buf.stable_mut_ptr(), total, peek) });
+				match ret {
+					Ok(cnt) if this.exact && cnt < total => this.pos = cnt,
+					ret => {
+						let mut buf = this.buf.take().unwrap();
+						if let Ok(cnt) = ret {
+							// SAFETY: just copied these bytes in
+							unsafe { buf.set_init(cnt) };
+						}
+						return Poll::Ready((ret, buf));
+					}
+				}
+			}
+		}
+
+		loop {
+			let buf = this.buf.as_mut().expect("polled after completion");
+			let total = buf.bytes_total();
+			// SAFETY: pos never goes past the end of the buffer
+			let ptr = unsafe { buf.stable_mut_ptr().add(this.pos) };
+
+			this.submitted = true;
+			// SAFETY: the buffer is handed to the runtime if we are dropped before the op completes
+			let ret = ready!(unsafe {
+				this.io
+					.poll_recv_flags(cx, ptr, total - this.pos, this.flags)
+			});
+
+			let ret = match ret {
+				Ok(0) if this.exact && this.pos < total => {
+					Err(io::Error::from(io::ErrorKind::UnexpectedEof))
+				}
+				Ok(cnt) => {
+					this.pos += cnt;
+					if this.exact && this.pos < total {
+						continue;
+					}
+					Ok(this.pos)
+				}
+				Err(err) => Err(err.into()),
+			};
+
+			let mut buf = this.buf.take().unwrap();
+			// SAFETY: kernel just initialized these bytes in the recv ops, or we copied them in
+			unsafe { buf.set_init(this.pos) };
+
+			return Poll::Ready((ret, buf));
+		}
+	}
+}
+
+impl<T: FlaggedRead + BufferedRead, B: IoBufMut> Drop for OwnedRecv<'_, T, B> {
+	fn drop(&mut self) {
+		if self.submitted
+			&& let Some(buf) = self.buf.take()
+		{
+			self.io.cancel_op(
+				T::READ_OP_ID,
+				OperationCancelData {
+					wake: true,
+					buf: Box::new(buf),
+					release: None,
+				},
+			);
+		}
+	}
+}
+
+pub(crate) struct OwnedWrite<'a, T: BufferedWrite, B: IoBuf> {
+	io: &'a mut T,
+	buf: Option<B>,
+	written: usize,
+	all: bool,
+	submitted: bool,
+}
+
+impl<'a, T: BufferedWrite, B: IoBuf> OwnedWrite<'a, T, B> {
+	pub fn new(io: &'a mut T, buf: B, all: bool) -> Self {
+		Self {
+			io,
+			buf: Some(buf),
+			written: 0,
+			all,
+			submitted: false,
+		}
+	}
+}
+
+impl<T: BufferedWrite, B: IoBuf> Future for OwnedWrite<'_, T, B> {
+	type Output = (io::Result<usize>, B);
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		let this = &mut *self;
+		let buf = this.buf.as_ref().expect("polled after completion");
+
+		if !this.submitted
+			&& let Err(err) = ready!(WriteBuffer::poll_prepare(this.io, cx))
+		{
+			return Poll::Ready((Err(err), this.buf.take().unwrap()));
+		}
+
+		let ret = loop {
+			let remaining = buf.bytes_init() - this.written;
+
+			// SAFETY: written is never larger than the initialized part of the buffer
+			let ptr = unsafe { buf.stable_ptr().add(this.written) };
+			this.submitted = true;
+			// SAFETY: the buffer is handed to the runtime if we are dropped before the op completes
+			match ready!(unsafe { this.io.poll_write_raw(cx, ptr, remaining, buf.buf_index()) }) {
+				Ok(0) if this.all && remaining > 0 => break Err(io::ErrorKind::WriteZero.into()),
+				Ok(cnt) if this.all && cnt < remaining => this.written += cnt,
+				Ok(cnt) => break Ok(this.written + cnt),
+				Err(err) => break Err(err.into()),
+			}
+		};
+
+		Poll::Ready((ret, this.buf.take().unwrap()))
+	}
+}
+
+impl<T: BufferedWrite, B: IoBuf> Drop for OwnedWrite<'_, T, B> {
+	fn drop(&mut self) {
+		if self.submitted
+			&& let Some(buf) = self.buf.take()
+		{
+			self.io.cancel_op(
+				T::WRITE_OP_ID,
+				OperationCancelData {
+					wake: true,
+					buf: Box::new(buf),
+					release: None,
+				},
+			);
+		}
+	}
+}
+
+/// iovecs pointing into the buffers of a vectored op, owned by the op along with them.
+struct IoVecs(Box<[libc::iovec]>);
+
+// SAFETY: the iovecs only point into buffers owned by the same op, which are Send
+unsafe impl Send for IoVecs {}
+
+impl IoVecs {
+	fn cnt(&self) -> io::Result<u32> {
+		u32::try_from(self.0.len()).map_err(|_| Error::BufferTooLarge.into())
+	}
+}
+
+pub(crate) struct OwnedReadv<'a, T: VectoredRead + BufferedRead, B: IoBufMut> {
+	io: &'a mut T,
+	data: Option<(Vec<B>, IoVecs)>,
+	submitted: bool,
+}
+
+impl<'a, T: VectoredRead + BufferedRead, B: IoBufMut> OwnedReadv<'a, T, B> {
+	pub fn new(io: &'a mut T, mut bufs: Vec<B>) -> Self {
+		let iovecs = bufs
+			.iter_mut()
+			.map(|buf| libc::iovec {
+				iov_base: buf.stable_mut_ptr().cast(),
+				iov_len: buf.bytes_total(),
+			})
+			.collect();
+		Self {
+			io,
+			data: Some((bufs, IoVecs(iovecs))),
+			submitted: false,
+		}
+	}
+}
+
+impl<T: VectoredRead + BufferedRead, B: IoBufMut> Future for OwnedReadv<'_, T, B> {
+	type Output = (io::Result<usize>, Vec<B>);
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		let this = &mut *self;
+		let (bufs, iovecs) = this.data.as_mut().expect("polled after completion");
+
+		if !this.submitted {
+			let ret = ready!(ReadBuffer::poll_prepare(this.io, cx));
+			let read_buf = this.io.read_buffer();
+			if ret.is_err() || !read_buf.is_empty() {
+				let ret = ret.map(|()| {
+					let mut cnt = 0;
+					for buf in bufs.iter_mut() {
+						// SAFETY: the buffer has room for its total capacity
+						let filled = unsafe {
+							read_buf.copy_out(buf.stable_mut_ptr(), buf.bytes_total(), false)
+						};
+						// SAFETY: just copied these bytes in
+						unsafe { buf.set_init(filled) };
+						cnt += filled;
+					}
+					cnt
+				});
+				let (bufs, _) = this.data.take().unwrap();
+				return Poll::Ready((ret, bufs));
+			}
+		}
+
+		this.submitted = true;
+		let ret = match iovecs.cnt() {
+			// SAFETY: the buffers and iovecs are handed to the runtime if we are dropped before
+			// the op completes
+			Ok(cnt) => ready!(unsafe { this.io.poll_readv_raw(cx, iovecs.0.as_ptr(), cnt) })
+				.map_err(Into::into),
+			Err(err) => Err(err),
+		};
+
+		let (mut bufs, _) = this.data.take().unwrap();
+		if let Ok(cnt) = ret {
+			let mut remaining = cnt;
+			for buf in &mut bufs {
+				let filled = remaining.min(buf.bytes_total());
+				// SAFETY: kernel just initialized these bytes in the read op
+				unsafe { buf.set_init(filled) };
+				remaining -= filled;
+			}
+		}
+
+		Poll::Ready((ret, bufs))
+	}
+}
+
+impl<T: VectoredRead + BufferedRead, B: IoBufMut> Drop for OwnedReadv<'_, T, B> {
+	fn drop(&mut self) {
+		if self.submitted
+			&& let Some(data) = self.data.take()
+		{
+			self.io.cancel_op(
+				T::READ_OP_ID,
+				OperationCancelData {
+					wake: true,
+					buf: Box::new(data),
+					release: None,
+				},
+			);
+		}
+	}
+}
+
+pub(crate) struct OwnedWritev<'a, T: VectoredWrite + BufferedWrite, B: IoBuf> {
+	io: &'a mut T,
+	data: Option<(Vec<B>, IoVecs)>,
+	submitted: bool,
+}
+
+impl<'a, T: VectoredWrite + BufferedWrite, B: IoBuf> OwnedWritev<'a, T, B> {
+	pub fn new(io: &'a mut T, bufs: Vec<B>) -> Self {
+		let iovecs = bufs
+			.iter()
+			.map(|buf| libc::iovec {
+				iov_base: buf.stable_ptr().cast_mut().cast(),
+				iov_len: buf.bytes_init(),
+			})
+			.collect();
+		Self {
+			io,
+			data: Some((bufs, IoVecs(iovecs))),
+			submitted: false,
+		}
+	}
+}
+
+impl<T: VectoredWrite + BufferedWrite, B: IoBuf> Future for OwnedWritev<'_, T, B> {
+	type Output = (io::Result<usize>, Vec<B>);
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		let this = &mut *self;
+		let (_, iovecs) = this.data.as_ref().expect("polled after completion");
+
+		if !this.submitted
+			&& let Err(err) = ready!(WriteBuffer::poll_prepare(this.io, cx))
+		{
+			let (bufs, _) = this.data.take().unwrap();
+			return Poll::Ready((Err(err), bufs));
+		}
+
+		this.submitted = true;
+		let ret = match iovecs.cnt() {
+			// SAFETY: the buffers and iovecs are handed to the runtime if we are dropped before
+			// the op completes
+			Ok(cnt) => ready!(unsafe { this.io.poll_writev_raw(cx, iovecs.0.as_ptr(), cnt) })
+				.map_err(Into::into),
+			Err(err) => Err(err),
+		};
+
+		let (bufs, _) = this.data.take().unwrap();
+		Poll::Ready((ret, bufs))
+	}
+}
+
+impl<T: VectoredWrite + BufferedWrite, B: IoBuf> Drop for OwnedWritev<'_, T, B> {
+	fn drop(&mut self) {
+		if self.submitted
+			&& let Some(data) = self.data.take()
+		{
+			self.io.cancel_op(
+				T::WRITE_OP_ID,
+				OperationCancelData {
+					wake: true,
+					buf: Box::new(data),
+					release: None,
+				},
+			);
+		}
+	}
+}
+
+pub(crate) struct OwnedSendZc<'a, T: ZeroCopyWrite, B: IoBuf> {
+	io: &'a mut T,
+	buf: Option<B>,
+	submitted: bool,
+}
+
+impl<'a, T: ZeroCopyWrite, B: IoBuf> OwnedSendZc<'a, T, B> {
+	pub fn new(io: &'a mut T, buf: B) -> Self {
+		Self {
+			io,
+			buf: Some(buf),
+			submitted: false,
+		}
+	}
+}
+
+impl<T: ZeroCopyWrite, B: IoBuf> Future for OwnedSendZc<'_, T, B> {
+	type Output = (io::Result<usize>, B);
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		let this = &mut *self;
+		let buf = this.buf.as_ref().expect("polled after completion");
+
+		if !this.submitted
+			&& let Err(err) = ready!(WriteBuffer::poll_prepare(this.io, cx))
+		{
+			return Poll::Ready((Err(err), this.buf.take().unwrap()));
+		}
+
+		let (ptr, len, index) = (buf.stable_ptr(), buf.bytes_init(), buf.buf_index());
+		this.submitted = true;
+		// SAFETY: the buffer is handed to the runtime if we are dropped before the kernel releases
+		// it
+		let ret = ready!(unsafe { this.io.poll_send_zc(cx, ptr, len, index) });
+
+		Poll::Ready((ret.map_err(Into::into), this.buf.take().unwrap()))
+	}
+}
+
+impl<T: ZeroCopyWrite, B: IoBuf> Drop for OwnedSendZc<'_, T, B> {
+	fn drop(&mut self) {
+		// kept until the notification, even if the result already arrived
+		if self.submitted
+			&& let Some(buf) = self.buf.take()
+		{
+			self.io.cancel_op(
+				T::WRITE_OP_ID,
+				OperationCancelData {
+					wake: true,
+					buf: Box::new(buf),
+					release: None,
+				},
+			);
+		}
+	}
+}
+
+/// Take the lease on the buffer the kernel picked for a completion, or `None` at EOF.
+fn provided_buf<T: ProvidedRead>(io: &T, cnt: u32, flags: u32) -> io::Result<Option<ProvidedBuf>> {
+	let Some(bid) = cqueue::buffer_select(flags) else {
+		return Ok(None);
+	};
+	let ring = io
+		.get_rt()
+		.load()
+		.ok_or(Error::NoRuntime)?
+		.buf_ring()?
+		.clone();
+
+	// dropping an empty lease gives the buffer straight back
+	let buf = ProvidedBuf::new(ring, bid, cnt as usize);
+	Ok((!buf.is_empty()).then_some(buf))
+}
+
+pub(crate) struct OwnedProvidedRead<'a, T: ProvidedRead + BufferedRead> {
+	io: &'a mut T,
+	submitted: bool,
+	done: bool,
+}
+
+impl<'a, T: ProvidedRead + BufferedRead> OwnedProvidedRead<'a, T> {
+	pub fn new(io: &'a mut T) -> Self {
+		Self {
+			io,
+			submitted: false,
+			done: false,
+		}
+	}
+}
+
+impl<T: ProvidedRead + BufferedRead> Future for OwnedProvidedRead<'_, T> {
+	type Output = io::Result<Option<ProvidedBuf>>;
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		let this = &mut *self;
+		if !this.submitted {
+			ready!(ReadBuffer::poll_prepare(this.io, cx))?;
+			if !this.io.read_buffer().is_empty() {
+				this.done = true;
+				return Poll::Ready(Err(Error::ReadBuffered.into()));
+			}
+		}
+
+		this.submitted = true;
+		let ret = ready!(this.io.poll_read_provided(cx));
+		this.done = true;
+
+		let (cnt, flags) = ret?;
+		Poll::Ready(provided_buf(this.io, cnt, flags))
+	}
+}
+
+impl<T: ProvidedRead + BufferedRead> Drop for OwnedProvidedRead<'_, T> {
+	fn drop(&mut self) {
+		if self.submitted && !self.done {
+			// the runtime gives the buffer back to the ring if the kernel picked one
+			self.io.cancel_op(
+				T::READ_OP_ID,
+				OperationCancelData {
+					wake: true,
+					buf: Box::new(()),
+					release: None,
+				},
+			);
+		}
+	}
+}
+
+/// Stream of buffers received by a multishot recv, which is re-armed whenever the kernel ends it.
+pub(crate) struct RecvStream<'a, T: ProvidedRead + BufferedRead> {
+	io: &'a mut T,
+	// whether the current recv yielded anything, so running out of buffers is worth retrying
+	received: bool,
+	submitted: bool,
+	done: bool,
+}
+
+impl<'a, T: ProvidedRead + BufferedRead> RecvStream<'a, T> {
+	pub fn new(io: &'a mut T) -> Self {
+		Self {
+			io,
+			received: false,
+			submitted: false,
+			done: false,
+		}
+	}
+}
+
+impl<T: ProvidedRead + BufferedRead> Stream for RecvStream<'_, T> {
+	type Item = io::Result<ProvidedBuf>;
+
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+		let this = &mut *self;
+		if this.done {
+			return Poll::Ready(None);
+		}
+
+		if !this.submitted {
+			if let Err(err) = ready!(ReadBuffer::poll_prepare(this.io, cx)) {
+				return Poll::Ready(Some(Err(err)));
+			}
+			if !this.io.read_buffer().is_empty() {
+				this.done = true;
+				return Poll::Ready(Some(Err(Error::ReadBuffered.into())));
+			}
+		}
+
+		loop {
+			this.submitted = true;
+			let ret = match ready!(this.io.poll_recv_multi(cx)) {
+				Ok((cnt, flags)) => {
+					// the next poll re-arms the recv if the kernel ended it
+					this.received = cqueue::more(flags);
+					provided_buf(this.io, cnt, flags)
+				}
+				// the kernel ends the recv once the ring runs dry, which only means the caller
+				// has to give buffers back first if this recv didn't get any
+				Err(Error::Io(err))
+					if err.raw_os_error() == Some(libc::ENOBUFS)
+						&& std::mem::take(&mut this.received) =>
+				{
+					continue;
+				}
+				Err(err) => {
+					this.received = false;
+					Err(err.into())
+				}
+			};
+
+			return match ret {
+				Ok(Some(buf)) => Poll::Ready(Some(Ok(buf))),
+				Ok(None) => {
+					this.done = true;
+					Poll::Ready(None)
+				}
+				Err(err) => Poll::Ready(Some(Err(err))),
+			};
+		}
+	}
+}
+
+impl<T: ProvidedRead + BufferedRead> Drop for RecvStream<'_, T> {
+	fn drop(&mut self) {
+		// the runtime gives buffers of completions nobody saw back to the ring
+		if self.submitted {
+			self.io.cancel_op(
+				T::READ_OP_ID,
+				OperationCancelData {
+					wake: true,
+					buf: Box::new(()),
+					release: None,
+				},
+			);
+		}
+	}
+}
+
+/// Sets or gets a socket option through the ring once buffered writes went out, falling back to
+/// plain `setsockopt`/`getsockopt` where the kernel can't do it.
+pub(crate) struct OwnedSockOpt<'a, T: RawSockOpt, V: Copy + Send + 'static> {
+	io: &'a mut T,
+	// boxed so it doesn't move while the kernel reads or writes it
+	val: Option<Box<V>>,
+	get: bool,
+	level: i32,
+	name: i32,
+	flushed: bool,
+	submitted: bool,
+}
+
+impl<'a, T: RawSockOpt, V: Copy + Send + 'static> OwnedSockOpt<'a, T, V> {
+	fn new(io: &'a mut T, get: bool, level: i32, name: i32, val: V) -> Self {
+		Self {
+			io,
+			val: Some(Box::new(val)),
+			get,
+			level,
+			name,
+			flushed: false,
+			submitted: false,
+		}
+	}
+
+	pub fn set(io: &'a mut T, level: i32, name: i32, val: V) -> Self {
+		Self::new(io, false, level, name, val)
+	}
+
+	/// `init` is what the kernel writes the option into.
+	pub fn get(io: &'a mut T, level: i32, name: i32, init: V) -> Self {
+		Self::new(io, true, level, name, init)
+	}
+
+	fn fallback(&self, val: V) -> io::Result<V> {
+		let fd = self.io.as_raw_fd();
+		if self.get {
+			sockopt::getsockopt(fd, self.level, self.name)
+		} else {
+			sockopt::setsockopt(fd, self.level, self.name, val).map(|()| val)
+		}
+	}
+}
+
+impl<T: RawSockOpt, V: Copy + Send + 'static> Future for OwnedSockOpt<'_, T, V> {
+	type Output = io::Result<V>;
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		let this = &mut *self;
+		if !this.flushed {
+			ready!(WriteBuffer::poll_prepare(this.io, cx))?;
+			this.flushed = true;
+		}
+
+		let val = this.val.as_mut().expect("polled after completion");
+		// this only tells whether the ring knows `URING_CMD` at all, whether the socket supports
+		// the sockopt commands is decided by the `EOPNOTSUPP` fallback below
+		let supported = this
+			.io
+			.get_rt()
+			.load()
+			.is_some_and(|rt| rt.supports(io_uring::opcode::SetSockOpt::CODE));
+		if !supported {
+			let val = **val;
+			this.val = None;
+			return Poll::Ready(this.fallback(val));
+		}
+
+		let ptr = (&raw mut **val).cast();
+		let len = sockopt::socklen(size_of::<V>());
+		this.submitted = true;
+		// SAFETY: the value is handed to the runtime if we are dropped before the op completes
+		let ret = ready!(unsafe {
+			this.io
+				.poll_sockopt(cx, this.get, this.level, this.name, ptr, len)
+		});
+
+		let val = *this.val.take().unwrap();
+		Poll::Ready(match ret {
+			Ok(_) => Ok(val),
+			// sockets only support these commands on newer kernels, and only some levels
+			Err(Error::Io(err)) if err.raw_os_error() == Some(libc::EOPNOTSUPP) => {
+				this.fallback(val)
+			}
+			Err(err) => Err(err.into()),
+		})
+	}
+}
+
+impl<T: RawSockOpt, V: Copy + Send + 'static> Drop for OwnedSockOpt<'_, T, V> {
+	fn drop(&mut self) {
+		// until the flush is done the write op belongs to the write buffer
+		if self.submitted
+			&& let Some(val) = self.val.take()
+		{
+			self.io.cancel_op(
+				T::WRITE_OP_ID,
+				OperationCancelData {
+					wake: true,
+					buf: val,
+					release: None,
+				},
+			);
+		}
+	}
+}
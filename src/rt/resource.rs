@@ -1,5 +1,5 @@
 use std::{
-	os::fd::OwnedFd,
+	os::fd::{OwnedFd, RawFd},
 	sync::{
 		Arc,
 		atomic::{AtomicBool, Ordering},
@@ -7,11 +7,16 @@ use std::{
 };
 
 use futures::channel::oneshot;
+use io_uring::{opcode, types::Fixed};
 use slab::Slab;
 
 use crate::{Error, Result};
 
-use super::operation::Operations;
+use super::{
+	UringData,
+	inner::{RuntimeWorkerChannel, WorkerMessage},
+	operation::{BorrowedOperations, EventData, OperationCancelData, Operations},
+};
 
 pub(super) struct WorkerResourceSlab {
 	slab: Slab<WorkerResource>,
@@ -23,10 +28,15 @@ impl WorkerResourceSlab {
 	}
 
 	pub fn insert(&mut self, resource: WorkerResource) -> Result<u32> {
-		self.slab
-			.insert(resource)
-			.try_into()
-			.map_err(|_| Error::TooManyResources)
+		let entry = self.slab.vacant_entry();
+		// u32::MAX is reserved for untracked entries
+		match u32::try_from(entry.key()) {
+			Ok(id) if id != u32::MAX => {
+				entry.insert(resource);
+				Ok(id)
+			}
+			_ => Err(Error::TooManyResources),
+		}
 	}
 
 	pub fn get(&self, id: u32) -> Option<&WorkerResource> {
@@ -38,37 +48,150 @@ impl WorkerResourceSlab {
 	}
 }
 
-pub(super) struct WorkerResource {
-	pub fd: Option<OwnedFd>,
-	pub ops: Operations,
-	pub closing: Arc<AtomicBool>,
+/// Allocates slots in the registered file table.
+pub(super) struct FixedFiles {
+	free: Vec<u32>,
+	next: u32,
+	size: u32,
 }
 
-impl WorkerResource {
-	pub fn closing(&self) -> bool {
-		self.closing.load(Ordering::Acquire)
+impl FixedFiles {
+	pub fn new(size: u32) -> Self {
+		Self {
+			free: Vec::new(),
+			next: 0,
+			size,
+		}
+	}
+
+	/// Allocate a slot without a file, for ops that create one directly in the table.
+	pub fn reserve(&mut self) -> Result<u32> {
+		match self.free.pop() {
+			Some(slot) => Ok(slot),
+			None if self.next < self.size => {
+				self.next += 1;
+				Ok(self.next - 1)
+			}
+			None => Err(Error::TooManyResources),
+		}
+	}
+
+	pub fn insert(&mut self, rt: &UringData, fd: RawFd) -> Result<u32> {
+		let slot = self.reserve()?;
+
+		if let Err(err) = rt.uring.submitter().register_files_update(slot, &[fd]) {
+			self.free.push(slot);
+			return Err(err.into());
+		}
+
+		Ok(slot)
+	}
+
+	/// Only call this once no ops on the slot can be in flight.
+	pub fn remove(&mut self, rt: &UringData, slot: u32) {
+		// if this fails the slot keeps its file until it's reused, which replaces it
+		let _ = rt.uring.submitter().register_files_update(slot, &[-1]);
+		self.free.push(slot);
 	}
 }
 
+pub(super) struct WorkerResource {
+	// closed once the resource is removed, unless it's deregistered
+	pub fd: Option<OwnedFd>,
+	pub file: Option<Fixed>,
+	pub ops: Operations,
+}
+
 pub(super) type RegisterResourceSender = oneshot::Sender<Result<Resource>>;
+pub(super) type DeregisterResourceSender = oneshot::Sender<OwnedFd>;
 
 #[derive(Clone)]
 pub(crate) struct Resource<const SIZE: usize = 4> {
 	pub id: u32,
 	pub ops: Operations<SIZE>,
+	/// Slot in the registered file table, if the resource has an fd.
+	pub file: Option<Fixed>,
 	closing: Arc<AtomicBool>,
 }
 
+impl Resource {
+	/// Register an fd with the runtime, returning the resource and its slot in the file table.
+	pub async fn register_fd(sender: &RuntimeWorkerChannel, fd: OwnedFd) -> Result<(Self, Fixed)> {
+		let (tx, rx) = oneshot::channel();
+
+		sender.send(WorkerMessage::RegisterResource {
+			ops: Operations::new_from_size(),
+			fd: Some(fd),
+			complete: tx,
+		})?;
+
+		let resource = rx.await.map_err(|_| Error::NoRuntime)??;
+		let file = resource.file.expect("resource was registered with an fd");
+
+		Ok((resource, file))
+	}
+
+	/// Register a resource with an empty slot in the file table, which ops like `Socket` can
+	/// create a file in directly. The file is closed together with the resource.
+	pub async fn register_direct(sender: &RuntimeWorkerChannel) -> Result<(Self, Fixed)> {
+		let (tx, rx) = oneshot::channel();
+
+		sender.send(WorkerMessage::RegisterDirect {
+			ops: Operations::new_from_size(),
+			complete: tx,
+		})?;
+
+		let resource = rx.await.map_err(|_| Error::NoRuntime)??;
+		let file = resource.file.expect("resource was registered with a slot");
+
+		Ok((resource, file))
+	}
+
+	/// Remove a resource registered with `register_fd` from the runtime once its in-flight ops
+	/// complete, returning its fd instead of closing it.
+	pub async fn deregister_fd(&self, sender: &RuntimeWorkerChannel) -> Result<OwnedFd> {
+		let (tx, rx) = oneshot::channel();
+
+		sender.send(WorkerMessage::DeregisterResource {
+			resource: self.dup(),
+			complete: tx,
+		})?;
+
+		rx.await.map_err(|_| Error::NoRuntime)
+	}
+}
+
 impl<const SIZE: usize> Resource<SIZE> {
-	pub(super) fn new(id: u32, ops: Operations<SIZE>, closing: Arc<AtomicBool>) -> Self {
-		Self { id, ops, closing }
+	pub(super) fn new(
+		id: u32,
+		ops: Operations<SIZE>,
+		file: Option<Fixed>,
+		closing: Arc<AtomicBool>,
+	) -> Self {
+		Self {
+			id,
+			ops,
+			file,
+			closing,
+		}
 	}
 
 	pub fn dup(&self) -> Self {
 		Self {
 			id: self.id,
 			ops: self.ops.dup(),
-			closing: self.closing.clone()
+			file: self.file,
+			closing: self.closing.clone(),
+		}
+	}
+
+	/// Like `dup`, see [`Operations::dup_only`].
+	pub fn dup_only(&self, ids: &[u32]) -> Self {
+		Self {
+			id: self.id,
+			ops: self.ops.dup_only(ids),
+			file: self.file,
+			closing: self.closing.clone(),
 		}
 	}
 
@@ -79,4 +202,107 @@ impl<const SIZE: usize> Resource<SIZE> {
 	pub fn set_closing(&self) {
 		self.closing.store(true, Ordering::Release);
 	}
+
+	/// Borrow the ops in `a` and `b` for handles that poll them in our place, see
+	/// [`Operations::split_mut`].
+	pub fn split_mut<const A: usize, const B: usize>(
+		&mut self,
+		a: [u32; A],
+		b: [u32; B],
+	) -> (BorrowedResource<'_, A, SIZE>, BorrowedResource<'_, B, SIZE>) {
+		let (a, b) = self.ops.split_mut(a, b);
+		(
+			BorrowedResource {
+				id: self.id,
+				ops: a,
+				closing: self.closing.clone(),
+			},
+			BorrowedResource {
+				id: self.id,
+				ops: b,
+				closing: self.closing.clone(),
+			},
+		)
+	}
+
+	/// Cancel an in-flight op, keeping `data` alive until the kernel completes it.
+	pub fn cancel(&mut self, rt: Option<&UringData>, id: u32, data: OperationCancelData) {
+		if self.detach(rt, id, data) {
+			submit_cancel(rt, self.id, id);
+		}
+	}
+
+	/// Stop waiting for an op without cancelling it, keeping `data` alive until it completes.
+	/// Returns true if the op is still in flight.
+	pub fn detach(&mut self, rt: Option<&UringData>, id: u32, data: OperationCancelData) -> bool {
+		let release = data.release;
+		self.ops.try_cancel(id, data, leftover(rt, release))
+	}
+
+	/// Ask the kernel to cancel an op we keep waiting for, so it completes early with
+	/// `ECANCELED`, also if it has a linked timeout, or whatever it got done before.
+	pub fn interrupt(&self, rt: Option<&UringData>, id: u32) {
+		if let Some(op) = self.ops.get(id) {
+			op.unlink_timeout();
+		}
+		submit_cancel(rt, self.id, id);
+	}
+}
+
+/// Ops of a resource borrowed with `split_mut`, with the same methods as the resource.
+pub(crate) struct BorrowedResource<'a, const N: usize, const SIZE: usize = 4> {
+	pub id: u32,
+	pub ops: BorrowedOperations<'a, N, SIZE>,
+	closing: Arc<AtomicBool>,
+}
+
+impl<const N: usize, const SIZE: usize> BorrowedResource<'_, N, SIZE> {
+	pub fn closing(&self) -> bool {
+		self.closing.load(Ordering::Acquire)
+	}
+
+	/// See [`Resource::cancel`].
+	pub fn cancel(&mut self, rt: Option<&UringData>, id: u32, data: OperationCancelData) {
+		if self.detach(rt, id, data) {
+			submit_cancel(rt, self.id, id);
+		}
+	}
+
+	/// See [`Resource::detach`].
+	pub fn detach(&mut self, rt: Option<&UringData>, id: u32, data: OperationCancelData) -> bool {
+		let release = data.release;
+		self.ops.try_cancel(id, data, leftover(rt, release))
+	}
+
+	/// See [`Resource::interrupt`].
+	pub fn interrupt(&self, rt: Option<&UringData>, id: u32) {
+		if let Some(op) = self.ops.get(id) {
+			op.unlink_timeout();
+		}
+		submit_cancel(rt, self.id, id);
+	}
+}
+
+fn submit_cancel(rt: Option<&UringData>, resource: u32, id: u32) {
+	if let Some(rt) = rt {
+		let entry = opcode::AsyncCancel::new(EventData { resource, id }.into())
+			.build()
+			.user_data(EventData::UNTRACKED);
+
+		// SAFETY: AsyncCancel doesn't reference any memory.
+		// if this fails the op will still complete eventually
+		let _ = unsafe { rt.submit(&entry) };
+	}
+}
+
+/// Handles completions of a detached op that nobody will see.
+fn leftover(rt: Option<&UringData>, release: Option<fn(i32)>) -> impl FnMut(i32, u32) {
+	move |val, flags| {
+		if let Some(release) = release {
+			release(val);
+		}
+		if let Some(rt) = rt {
+			rt.recycle_buffer(flags);
+		}
+	}
 }
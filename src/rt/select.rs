@@ -9,9 +9,10 @@ use futures::{
 use pin_project_lite::pin_project;
 
 /// Type to tell [`SelectWithStrategy`] which stream to poll next.
-#[derive(Debug, PartialEq, Eq, Copy, Clone, Hash)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Hash, Default)]
 pub enum PollNext {
 	/// Poll the first stream.
+	#[default]
 	Left,
 	/// Poll the second stream.
 	Right,
@@ -33,12 +34,6 @@ impl PollNext {
 	}
 }
 
-impl Default for PollNext {
-	fn default() -> Self {
-		Self::Left
-	}
-}
-
 enum InternalState {
 	Start,
 	LeftFinished,
@@ -96,7 +91,7 @@ pin_project! {
 /// ### Priority
 /// This example shows how to always prioritize the left stream.
 ///
-/// ```rust
+/// ```rust,ignore
 /// # futures::executor::block_on(async {
 /// use futures::stream::{ repeat, select_with_strategy, PollNext, StreamExt };
 ///
@@ -122,7 +117,7 @@ pin_project! {
 /// This example shows how to select from both streams round robin.
 /// Note: this special case is provided by [`futures-util::stream::select`].
 ///
-/// ```rust
+/// ```rust,ignore
 /// # futures::executor::block_on(async {
 /// use futures::stream::{ repeat, select_with_strategy, PollNext, StreamExt };
 ///
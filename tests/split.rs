@@ -0,0 +1,52 @@
+use std::{io::Read, time::Duration};
+
+use async_uring::{
+	net::{TcpListener, TcpStream},
+	rt::UringRuntime,
+	tokio::TokioAsyncFd,
+};
+use tokio::task::coop::unconstrained;
+
+async fn pair(rt: &UringRuntime) -> (TcpStream, std::net::TcpStream) {
+	let mut listener = TcpListener::bind(rt, "127.0.0.1:0".parse().unwrap())
+		.await
+		.unwrap();
+	let peer = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+	let (stream, _) = listener.accept().await.unwrap();
+	(stream, peer)
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn reunite_with_detached_write() {
+	let (rt, fut) = UringRuntime::builder::<TokioAsyncFd>().build().unwrap();
+	let worker = tokio::spawn(unconstrained(fut));
+	let (stream, mut peer) = pair(&rt).await;
+
+	// bigger than the socket buffers, so the write stays in flight until the peer reads
+	let len = 16 << 20;
+	let (read, mut write) = stream.into_split();
+	let mut send = Box::pin(write.write_all(vec![1u8; len]));
+	assert!(futures::poll!(send.as_mut()).is_pending());
+	std::mem::forget(send);
+
+	let stream = read.reunite(write).unwrap();
+	drop(stream);
+
+	// the leaked write_all never resubmits the rest of a short send, but the socket is only
+	// closed after the send it left in flight completed
+	let received = tokio::task::spawn_blocking(move || {
+		let mut buf = Vec::new();
+		peer.read_to_end(&mut buf).map(|_| buf)
+	})
+	.await
+	.unwrap()
+	.unwrap();
+	assert!(!received.is_empty() && received.len() <= len);
+	assert!(received.iter().all(|&x| x == 1));
+
+	// the worker panics if a completion arrives for a resource it already removed
+	tokio::time::sleep(Duration::from_millis(50)).await;
+	assert!(!worker.is_finished());
+	rt.stop().unwrap();
+	worker.await.unwrap().unwrap();
+}